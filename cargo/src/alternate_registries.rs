@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::Opts;
+
+/// A registry other than crates.io that dependencies refer to by name, e.g.
+/// `foo = { version = "1", registry = "my-registry" }`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlternateRegistry {
+    pub name: String,
+    /// Index URL as cargo expects it in `[registries]`.
+    pub index: String,
+}
+
+impl AlternateRegistry {
+    /// Parse a registry definition of the form `name=index`. The index is
+    /// either a URL or the path to a local git repository of a registry index,
+    /// which is turned into a `file://` URL.
+    pub fn parse(definition: &str) -> io::Result<Self> {
+        let Some((name, index)) = definition.split_once('=') else {
+            let msg = format!(
+                "Invalid registry `{definition}`. Expected `name=index` with an index URL or path."
+            );
+            error!(msg);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        };
+        let (name, index) = (name.trim(), index.trim());
+        if name.is_empty() || index.is_empty() {
+            let msg = format!(
+                "Invalid registry `{definition}`. Expected `name=index` with an index URL or path."
+            );
+            error!(msg);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        let index = if index.starts_with("sparse+") || url::Url::parse(index).is_ok() {
+            index.to_string()
+        } else {
+            local_index_url(Path::new(index))?
+        };
+        Ok(AlternateRegistry {
+            name: name.to_string(),
+            index,
+        })
+    }
+
+    pub fn from_opts(opts: &Opts) -> io::Result<Vec<Self>> {
+        opts.alternate_registry
+            .iter()
+            .map(|definition| AlternateRegistry::parse(definition))
+            .collect()
+    }
+}
+
+// NOTE: Cargo can only read a local index through git. A sparse index needs
+// an HTTP server.
+fn local_index_url(path: &Path) -> io::Result<String> {
+    if !path.join("config.json").is_file() {
+        let msg = format!("Registry index {} has no `config.json`.", path.display());
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+    }
+    if !path.join(".git").exists() {
+        let msg = format!(
+            "Registry index {} is not a git repository. Local indexes must be git repositories.",
+            path.display()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    let path = path.canonicalize()?;
+    url::Url::from_file_path(&path)
+        .map(|url| url.to_string())
+        .map_err(|_| {
+            let msg = format!("Can't turn {} into a file URL.", path.display());
+            error!(msg);
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })
+}
+
+/// The `[registries]` table defining the registries.
+pub fn registries_config(registries: &[AlternateRegistry]) -> toml::Table {
+    let mut definitions = toml::Table::new();
+    for registry in registries {
+        let mut definition = toml::Table::new();
+        definition.insert(
+            "index".to_string(),
+            toml::Value::String(registry.index.clone()),
+        );
+        definitions.insert(registry.name.clone(), toml::Value::Table(definition));
+    }
+    let mut config = toml::Table::new();
+    config.insert("registries".to_string(), toml::Value::Table(definitions));
+    config
+}
+
+fn to_toml_string(config: &toml::Table) -> io::Result<String> {
+    toml::to_string(config).map_err(|err| {
+        error!(?err);
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    })
+}
+
+/// Merge tables into the `config.toml` of the cargo home. Existing keys are
+/// kept unless the same key is set again.
+pub(crate) fn extend_cargo_home_config(cargo_home: &Path, extra: toml::Table) -> io::Result<()> {
+    let config_file = cargo_home.join("config.toml");
+    let mut config = if config_file.is_file() {
+        toml::from_str::<toml::Table>(&fs::read_to_string(&config_file)?).map_err(|err| {
+            error!(?err, ?config_file);
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?
+    } else {
+        toml::Table::new()
+    };
+    for (key, value) in extra {
+        match (config.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => {
+                existing.extend(value);
+            }
+            (_, value) => {
+                config.insert(key, value);
+            }
+        }
+    }
+    fs::create_dir_all(cargo_home)?;
+    fs::write(config_file, to_toml_string(&config)?)
+}
+
+/// Define the alternate registries in the temporary `CARGO_HOME` so that
+/// `cargo fetch` and `cargo vendor` can resolve dependencies from them.
+pub fn configure_alternate_registries(cargo_home: &Path, opts: &Opts) -> io::Result<()> {
+    let registries = AlternateRegistry::from_opts(opts)?;
+    if registries.is_empty() {
+        return Ok(());
+    }
+    for registry in &registries {
+        info!(
+            name = registry.name,
+            index = registry.index,
+            "🏛️ Adding registry."
+        );
+    }
+    extend_cargo_home_config(cargo_home, registries_config(&registries))
+}
+
+/// Add the registry definitions to the configuration printed by
+/// `cargo vendor`. Cargo still needs to know the index of a registry that is
+/// referred to by name, even though its crates come from the vendor directory.
+pub fn add_registries_to_vendor_config(cargo_config: &str, opts: &Opts) -> io::Result<String> {
+    let registries = AlternateRegistry::from_opts(opts)?;
+    if registries.is_empty() {
+        return Ok(cargo_config.to_string());
+    }
+    let mut cargo_config = cargo_config.trim_end().to_string();
+    cargo_config.push_str("\n\n");
+    cargo_config.push_str(&to_toml_string(&registries_config(&registries))?);
+    Ok(cargo_config)
+}
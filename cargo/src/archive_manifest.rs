@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::utils;
+use serde::{Deserialize, Serialize};
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+use crate::cli::Method;
+use crate::cli::Opts;
+use crate::cli::RegistryLayout;
+use crate::dependency_changes::extract_to_tempdir;
+use crate::dependency_changes::source_of;
+use crate::upstream_hints::UpstreamHints;
+
+/// Name of the manifest at the top level of the vendor or registry tarball.
+pub const MANIFEST_FILE_NAME: &str = ".obs-service-cargo.json";
+
+/// The options the tarball was created with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManifestOptions {
+    pub update: bool,
+    pub update_crate: Vec<String>,
+    pub respect_lockfile: bool,
+    pub tag: Option<String>,
+    pub custom_root: Option<String>,
+    pub manifest_path: Vec<PathBuf>,
+    pub no_root_manifest: bool,
+    pub filter: bool,
+    pub versioned_dirs: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry_layout: Option<RegistryLayout>,
+    /// Upstream hints that change which crates are vendored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_hints: Option<UpstreamHints>,
+}
+
+impl From<&Opts> for ManifestOptions {
+    fn from(opts: &Opts) -> Self {
+        let vendor_specific_args = opts.vendor_specific_args.as_ref().unwrap_or_default();
+        let is_vendor = matches!(opts.method, Method::Vendor);
+        ManifestOptions {
+            update: opts.update,
+            update_crate: opts.update_crate.clone(),
+            respect_lockfile: opts.respect_lockfile,
+            tag: opts.tag.clone(),
+            custom_root: opts.custom_root.clone(),
+            manifest_path: opts.manifest_path.clone(),
+            no_root_manifest: !is_vendor && opts.no_root_manifest.unwrap_or_default(),
+            filter: is_vendor && vendor_specific_args.filter,
+            versioned_dirs: is_vendor && vendor_specific_args.versioned_dirs,
+            // NOTE: Left out for the default layout, so manifests of older
+            // tarballs still match.
+            registry_layout: match opts.registry_layout {
+                RegistryLayout::LocalRegistry if !is_vendor => Some(opts.registry_layout),
+                _ => None,
+            },
+            upstream_hints: opts.upstream_hints.clone(),
+        }
+    }
+}
+
+/// The sources the tarball was vendored from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManifestSource {
+    /// File name of the source tarball or directory, or the URL of the git
+    /// repository.
+    pub name: String,
+    /// blake3 hash of the source tarball.
+    pub blake3: Option<String>,
+    pub revision: Option<String>,
+}
+
+impl ManifestSource {
+    fn from_opts(opts: &Opts) -> io::Result<Self> {
+        if url::Url::parse(&opts.src).is_ok() {
+            return Ok(ManifestSource {
+                name: opts.src.clone(),
+                blake3: None,
+                revision: opts.revision.clone(),
+            });
+        }
+        let src =
+            utils::process_globs(Path::new(&opts.src)).unwrap_or_else(|_| PathBuf::from(&opts.src));
+        let name = src
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| opts.src.clone());
+        let blake3 = if src.is_file() {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(fs::File::open(&src)?)?;
+            Some(hasher.finalize().to_hex().to_string())
+        } else {
+            None
+        };
+        Ok(ManifestSource {
+            name,
+            blake3,
+            revision: None,
+        })
+    }
+}
+
+/// A vendored crate and where it came from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManifestCrate {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    /// sha256 checksum of the crate file from the lockfile. Git dependencies
+    /// have none.
+    pub checksum: Option<String>,
+}
+
+/// How a tarball was produced. Written into the tarball so that later runs
+/// and other tools can work from the tarball alone. The content is
+/// deterministic and only changes if the tarball would.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ArchiveManifest {
+    pub obs_service_cargo_version: String,
+    pub method: String,
+    pub options: ManifestOptions,
+    pub source: ManifestSource,
+    /// blake3 hashes of the lockfiles after the update, keyed by their path
+    /// relative to the sources.
+    pub lockfiles: BTreeMap<PathBuf, String>,
+    pub crates: Vec<ManifestCrate>,
+}
+
+impl ArchiveManifest {
+    pub fn new(
+        setup_workdir: &Path,
+        lockfiles: &[impl AsRef<Path>],
+        opts: &Opts,
+    ) -> io::Result<Self> {
+        let canonical_setup_workdir = setup_workdir
+            .canonicalize()
+            .unwrap_or(setup_workdir.to_path_buf());
+        let mut hashes = BTreeMap::new();
+        let mut crates = BTreeSet::new();
+        for lockfile in lockfiles {
+            let lockfile = lockfile.as_ref();
+            if !lockfile.is_file() {
+                continue;
+            }
+            let relative_lockfile = lockfile
+                .strip_prefix(&canonical_setup_workdir)
+                .or_else(|_| lockfile.strip_prefix(setup_workdir))
+                .unwrap_or(lockfile)
+                .to_path_buf();
+            let hash = blake3::hash(&fs::read(lockfile)?);
+            hashes.insert(relative_lockfile, hash.to_hex().to_string());
+            for package in load_lockfile(lockfile)?.packages {
+                let Some(source) = source_of(&package) else {
+                    continue;
+                };
+                crates.insert(ManifestCrate {
+                    name: package.name.to_string(),
+                    version: package.version.to_string(),
+                    source,
+                    checksum: package.checksum.as_ref().map(ToString::to_string),
+                });
+            }
+        }
+        let method = match opts.method {
+            Method::Registry => "registry",
+            Method::Vendor => "vendor",
+        };
+        Ok(ArchiveManifest {
+            obs_service_cargo_version: env!("CARGO_PKG_VERSION").to_string(),
+            method: method.to_string(),
+            options: ManifestOptions::from(opts),
+            source: ManifestSource::from_opts(opts)?,
+            lockfiles: hashes,
+            crates: crates.into_iter().collect(),
+        })
+    }
+
+    /// Whether vendoring produced the same content. The source and the version
+    /// of obs-service-cargo are provenance only, since a new release of the
+    /// sources often locks the same dependencies.
+    pub fn has_same_content(&self, other: &ArchiveManifest) -> bool {
+        self.method == other.method
+            && self.options == other.options
+            && self.lockfiles == other.lockfiles
+    }
+
+    /// Write the manifest into the directory that becomes the tarball.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        let mut content = serde_json::to_string_pretty(self).map_err(|err| {
+            error!(?err, "Failed to serialize archive manifest.");
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?;
+        content.push('\n');
+        fs::write(dir.join(MANIFEST_FILE_NAME), content)
+    }
+
+    /// Read the manifest of an extracted tarball. Tarballs created by older
+    /// versions have none.
+    pub fn read(dir: &Path) -> io::Result<Option<Self>> {
+        let manifest_file = dir.join(MANIFEST_FILE_NAME);
+        if !manifest_file.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&manifest_file)?;
+        match serde_json::from_str(&content) {
+            Ok(manifest) => Ok(Some(manifest)),
+            Err(err) => {
+                warn!(
+                    ?err,
+                    ?manifest_file,
+                    "⚠️ Ignoring unreadable archive manifest."
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Read the manifest of a tarball.
+    pub fn read_from_archive(archive: &Path) -> io::Result<Option<Self>> {
+        let tempdir = extract_to_tempdir(archive)?;
+        ArchiveManifest::read(tempdir.path())
+    }
+}
+
+/// Whether the existing tarball was created the same way from the same
+/// lockfiles and can be kept as is. The tarball is looked up in
+/// `outdir` and then in the current directory. If it is only found in the
+/// current directory, it is copied to `outdir` unchanged.
+pub fn keep_unchanged_archive(manifest: &ArchiveManifest, opts: &Opts) -> io::Result<bool> {
+    if !opts.skip_unchanged {
+        return Ok(false);
+    }
+    let archive_name = opts.archive_name(&opts.compression)?;
+    let outdir_archive = opts.outdir.join(&archive_name);
+    let Some(existing_archive) = [outdir_archive.clone(), archive_name]
+        .into_iter()
+        .find(|path| path.is_file())
+    else {
+        info!("♻️ No existing archive found. Creating a new one.");
+        return Ok(false);
+    };
+    let Some(existing_manifest) = ArchiveManifest::read_from_archive(&existing_archive)? else {
+        info!(
+            ?existing_archive,
+            "♻️ Existing archive has no manifest. Creating a new one."
+        );
+        return Ok(false);
+    };
+    debug!(?existing_manifest, ?manifest);
+    if !existing_manifest.has_same_content(manifest) {
+        info!(
+            ?existing_archive,
+            "♻️ Method, lockfiles or options changed since the existing archive was created."
+        );
+        return Ok(false);
+    }
+    if existing_manifest.source != manifest.source
+        || existing_manifest.obs_service_cargo_version != manifest.obs_service_cargo_version
+    {
+        info!(
+            "♻️ The existing archive was vendored from {} by obs-service-cargo {}.",
+            existing_manifest.source.name, existing_manifest.obs_service_cargo_version
+        );
+    }
+    if existing_archive.canonicalize().ok() != outdir_archive.canonicalize().ok() {
+        fs::copy(&existing_archive, &outdir_archive)?;
+    }
+    info!(
+        ?existing_archive,
+        "♻️ Method, lockfiles and options did not change. Keeping the existing archive."
+    );
+    Ok(true)
+}
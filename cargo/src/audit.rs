@@ -32,6 +32,29 @@ use rustsec::{
 #[allow(unused_imports)]
 use tracing::{Level, debug, error, info, trace, warn};
 
+/// Source of the error returned if the lockfiles have vulnerabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VulnerabilitiesFound;
+
+impl std::fmt::Display for VulnerabilitiesFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            "Vulnerabilities found in application dependencies. These must be actioned to proceed with vendoring.",
+        )
+    }
+}
+
+impl std::error::Error for VulnerabilitiesFound {}
+
+impl VulnerabilitiesFound {
+    /// Whether the error was returned because the lockfiles have
+    /// vulnerabilities.
+    pub fn is_source_of(err: &io::Error) -> bool {
+        err.get_ref()
+            .is_some_and(|source| source.is::<VulnerabilitiesFound>())
+    }
+}
+
 pub fn process_reports(reports: Vec<Report>) -> Result<(), io::Error> {
     let mut passed = true;
 
@@ -81,14 +104,19 @@ pub fn process_reports(reports: Vec<Report>) -> Result<(), io::Error> {
 			 with vendoring."
         );
         Err(io::Error::new(
-			io::ErrorKind::Interrupted,
-			"Vulnerabilities found in application dependencies. These must be actioned to proceed with \
-			 vendoring."
-				.to_string(),
-		))
+            io::ErrorKind::Interrupted,
+            VulnerabilitiesFound,
+        ))
     }
 }
 
+pub fn load_lockfile(lockfile_path: &Path) -> io::Result<Lockfile> {
+    Lockfile::load(lockfile_path).map_err(|err| {
+        error!(?err, ?lockfile_path, "Failed to load lockfile.");
+        io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+    })
+}
+
 pub fn perform_cargo_audit(
     lockfiles: &[impl AsRef<Path>],
     exclude_ids: &[impl AsRef<str>],
@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use rustsec::cargo_lock::Lockfile;
+use rustsec::cargo_lock::Package;
+use serde::Serialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+use crate::cli::LintLevel;
+use crate::policy::BansPolicy;
+
+/// A package matching one of the denied crates of the policy.
+#[derive(Debug, Clone, Serialize)]
+pub struct BannedCrate {
+    pub name: String,
+    pub version: String,
+    /// The entry of the policy that matched.
+    pub denied_by: String,
+    pub reason: Option<String>,
+    pub lockfile: PathBuf,
+    pub dependents: Vec<String>,
+}
+
+/// A crate that was resolved at more than one version across the lockfiles.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateCrate {
+    pub name: String,
+    pub versions: Vec<DuplicateVersion>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateVersion {
+    pub version: String,
+    pub lockfiles: Vec<PathBuf>,
+    pub dependents: Vec<String>,
+}
+
+/// Packages of the lockfile that directly depend on the given package.
+fn dependents_of(lockfile: &Lockfile, package: &Package) -> Vec<String> {
+    lockfile
+        .packages
+        .iter()
+        .filter(|dependent| {
+            dependent
+                .dependencies
+                .iter()
+                .any(|dependency| dependency.matches(package))
+        })
+        .map(|dependent| format!("{} {}", dependent.name, dependent.version))
+        .collect()
+}
+
+/// Check every package of the lockfiles against the denied crates and look for
+/// crates resolved at multiple versions. Local packages are left out.
+pub fn check_bans(
+    setup_workdir: &Path,
+    lockfiles: &[impl AsRef<Path>],
+    bans: &BansPolicy,
+) -> io::Result<(Vec<BannedCrate>, Vec<DuplicateCrate>)> {
+    let mut banned = Vec::new();
+    let mut versions: BTreeMap<String, BTreeMap<semver::Version, DuplicateVersion>> =
+        BTreeMap::new();
+    for lockfile_path in lockfiles {
+        let lockfile_path = lockfile_path.as_ref();
+        let lockfile = load_lockfile(lockfile_path)?;
+        let relative_lockfile = lockfile_path
+            .strip_prefix(setup_workdir)
+            .unwrap_or(lockfile_path)
+            .to_path_buf();
+        for package in lockfile.packages.iter() {
+            if package.source.is_none() {
+                continue;
+            }
+            let name = package.name.as_str();
+            if let Some(spec) = bans
+                .deny
+                .iter()
+                .find(|spec| spec.matches(name, &package.version))
+            {
+                banned.push(BannedCrate {
+                    name: name.to_string(),
+                    version: package.version.to_string(),
+                    denied_by: spec.to_string(),
+                    reason: spec.reason.clone(),
+                    lockfile: relative_lockfile.clone(),
+                    dependents: dependents_of(&lockfile, package),
+                });
+            }
+            if bans
+                .skip
+                .iter()
+                .any(|spec| spec.matches(name, &package.version))
+            {
+                continue;
+            }
+            let duplicate = versions
+                .entry(name.to_string())
+                .or_default()
+                .entry(package.version.clone())
+                .or_insert_with(|| DuplicateVersion {
+                    version: package.version.to_string(),
+                    lockfiles: Vec::new(),
+                    dependents: Vec::new(),
+                });
+            duplicate.lockfiles.push(relative_lockfile.clone());
+            let dependents: BTreeSet<String> = duplicate
+                .dependents
+                .drain(..)
+                .chain(dependents_of(&lockfile, package))
+                .collect();
+            duplicate.dependents = dependents.into_iter().collect();
+        }
+    }
+    let duplicates = versions
+        .into_iter()
+        .filter(|(_, versions)| versions.len() > 1)
+        .map(|(name, versions)| DuplicateCrate {
+            name,
+            versions: versions.into_values().collect(),
+        })
+        .collect();
+    Ok((banned, duplicates))
+}
+
+/// Log the results of the bans check. Returns how many findings are denied.
+pub fn report_bans(
+    banned: &[BannedCrate],
+    duplicates: &[DuplicateCrate],
+    multiple_versions: LintLevel,
+) -> usize {
+    for banned_crate in banned {
+        error!(
+            "🛑 {} {} from {} is denied by `{}`{}.",
+            banned_crate.name,
+            banned_crate.version,
+            banned_crate.lockfile.display(),
+            banned_crate.denied_by,
+            banned_crate
+                .reason
+                .as_ref()
+                .map(|reason| format!(": {reason}"))
+                .unwrap_or_default()
+        );
+        if !banned_crate.dependents.is_empty() {
+            error!("  required by {}", banned_crate.dependents.join(", "));
+        }
+    }
+    if multiple_versions != LintLevel::Allow && !duplicates.is_empty() {
+        let message = format!(
+            "Found {} crates resolved at multiple versions:",
+            duplicates.len()
+        );
+        if multiple_versions == LintLevel::Deny {
+            error!("🛑 {}", message);
+        } else {
+            warn!("⚠️ {}", message);
+        }
+        for duplicate in duplicates {
+            info!("- {}", duplicate.name);
+            for version in &duplicate.versions {
+                info!(
+                    "  {} in {}{}",
+                    version.version,
+                    version
+                        .lockfiles
+                        .iter()
+                        .map(|lockfile| lockfile.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    if version.dependents.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" required by {}", version.dependents.join(", "))
+                    }
+                );
+            }
+        }
+    }
+    let denied_duplicates = if multiple_versions == LintLevel::Deny {
+        duplicates.len()
+    } else {
+        0
+    };
+    banned.len() + denied_duplicates
+}
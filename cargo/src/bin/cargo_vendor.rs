@@ -7,19 +7,23 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 // SPDX-License-Identifier: MPL-2.0
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use obs_service_cargo::cli;
+use obs_service_cargo::config_file::apply_config_file;
 
 use std::io;
 use std::io::IsTerminal;
+use std::path::Path;
 
 use terminfo::{Database, capability as cap};
 #[allow(unused_imports)]
 use tracing::{Level, debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut args = cli::Opts::parse();
+    let matches = cli::Opts::command().get_matches();
+    let mut args = cli::Opts::from_arg_matches(&matches)?;
 
     let terminfodb = Database::from_env().map_err(|e| {
         error!(err = ?e, "Unable to access terminfo db. This is a bug!");
@@ -42,7 +46,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let filter_layer = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
+    // NOTE: Keep stdout for the service definition.
+    let writer = if matches!(args.command, Some(cli::Command::ServiceDefinition(_))) {
+        BoxMakeWriter::new(io::stderr)
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+
     let builder = tracing_subscriber::fmt()
+        .with_writer(writer)
         .with_level(true)
         .with_ansi(to_color)
         .with_env_filter(filter_layer)
@@ -57,6 +69,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     builder.init();
 
     info!("🎢 Starting OBS Service Cargo Vendor.");
+    if args.command.is_none() {
+        apply_config_file(&mut args, &matches, Path::new(".")).inspect_err(|err| {
+            error!("{}", err);
+        })?;
+    }
     debug!(?args);
 
     warn!("⚠️  Cargo Vendor has been rewritten in rust!");
@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::utils;
+use serde::Serialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::VulnerabilitiesFound;
+use crate::changelog::changes_entry;
+use crate::changelog::changes_entry_lines;
+use crate::cli::BulkUpdateArgs;
+use crate::dependency_changes::archive_lockfiles;
+use crate::dependency_changes::compare_lockfiles;
+use crate::service_file::SERVICE_NAME;
+use crate::service_file::Service;
+use crate::service_file::ServiceFile;
+use crate::service_file::ServiceParam;
+use crate::service_file::SharedSources;
+use crate::service_file::service_opts;
+use crate::service_file::shared_source;
+
+/// Parameter names of the source, including the aliases of `src`.
+const SRC_PARAMS: &[&str] = &["src", "srctar", "srcdir", "target", "url"];
+
+/// How updating a package went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageStatus {
+    /// The lockfiles of a tarball changed or a tarball is new.
+    Changed,
+    Unchanged,
+    /// The lockfiles have vulnerabilities that are not accepted.
+    AuditFailed,
+    Error,
+}
+
+impl fmt::Display for PackageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageStatus::Changed => write!(f, "changed"),
+            PackageStatus::Unchanged => write!(f, "unchanged"),
+            PackageStatus::AuditFailed => write!(f, "audit failed"),
+            PackageStatus::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Outcome of updating one package.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageReport {
+    pub package: PathBuf,
+    pub status: PackageStatus,
+    /// Tarballs written to the package.
+    pub archives: Vec<PathBuf>,
+    /// Suggested entry for the `.changes` file, without the header.
+    pub changes_entry: Option<String>,
+    /// Errors of the services that failed.
+    pub errors: Vec<String>,
+}
+
+/// Find the source of a `src` parameter in the package directory. Like the
+/// old bulk update script, a value that matches no file is taken as the
+/// prefix of the tarball name, e.g. `zola` for `zola-0.17.2.tar.gz`.
+pub fn locate_source(package: &Path, src: &str) -> io::Result<String> {
+    if url::Url::parse(src).is_ok() {
+        return Ok(src.to_string());
+    }
+    let path = package.join(src);
+    if path.exists() {
+        return Ok(path.to_string_lossy().to_string());
+    }
+    if src.contains(['*', '?', '['])
+        && let Ok(found) = utils::process_globs(&path)
+    {
+        return Ok(found.to_string_lossy().to_string());
+    }
+    let mut candidates = Vec::new();
+    for entry in fs::read_dir(package)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if name.starts_with(src)
+            && name.contains(".tar")
+            && !name.contains("vendor")
+            && !name.contains("registry")
+            && !name.ends_with(".asc")
+        {
+            candidates.push(name);
+        }
+    }
+    candidates.sort();
+    match candidates.as_slice() {
+        [candidate] => Ok(package.join(candidate).to_string_lossy().to_string()),
+        _ => {
+            let msg = format!(
+                "Can't tell which tarball of {} is the source `{}`. Found {:?}.",
+                package.display(),
+                src,
+                candidates
+            );
+            error!(msg);
+            Err(io::Error::new(io::ErrorKind::NotFound, msg))
+        }
+    }
+}
+
+/// Result of running one service of a package.
+struct ServiceUpdate {
+    archive: Option<PathBuf>,
+    changed: bool,
+    lines: Vec<String>,
+}
+
+fn update_service(
+    service: &Service,
+    package: &Path,
+    args: &BulkUpdateArgs,
+    sources: &mut SharedSources,
+) -> io::Result<ServiceUpdate> {
+    let mut service = service.clone();
+    for param in service
+        .params
+        .iter_mut()
+        .filter(|param| SRC_PARAMS.contains(&param.name.as_str()))
+    {
+        param.value = locate_source(package, param.value.trim())?;
+    }
+    if let Some(crate_cache) = &args.crate_cache
+        && !service
+            .params
+            .iter()
+            .any(|param| param.name == "crate-cache")
+    {
+        service.params.push(ServiceParam {
+            name: "crate-cache".to_string(),
+            value: crate_cache.to_string_lossy().to_string(),
+        });
+    }
+    let mut opts = service_opts(&service, package, package)?;
+    let archive = package.join(opts.archive_name(&opts.compression)?);
+    let previous = if archive.is_file() {
+        Some(archive_lockfiles(&archive)?)
+    } else {
+        None
+    };
+    opts.extracted_src = shared_source(&opts, sources)?;
+    opts.run_vendor()?;
+    if !archive.is_file() {
+        return Ok(ServiceUpdate {
+            archive: None,
+            changed: false,
+            lines: Vec::new(),
+        });
+    }
+    let (_extracted, lockfiles) = archive_lockfiles(&archive)?;
+    let previous_lockfiles = previous
+        .as_ref()
+        .map(|(_, lockfiles)| lockfiles.clone())
+        .unwrap_or_default();
+    let changes = compare_lockfiles(&previous_lockfiles, &lockfiles, &opts.i_accept_the_risk)?;
+    Ok(ServiceUpdate {
+        archive: Some(archive),
+        changed: previous.is_none() || !changes.is_empty(),
+        lines: changes_entry_lines(&changes),
+    })
+}
+
+/// Run the `cargo_vendor` services of a package again, writing the tarballs
+/// into the package directory.
+pub fn update_package(package: &Path, args: &BulkUpdateArgs) -> PackageReport {
+    info!(?package, "🚚 Updating package.");
+    let mut report = PackageReport {
+        package: package.to_path_buf(),
+        status: PackageStatus::Unchanged,
+        archives: Vec::new(),
+        changes_entry: None,
+        errors: Vec::new(),
+    };
+    let service_file = package.join("_service");
+    if !service_file.is_file() {
+        report.status = PackageStatus::Error;
+        report
+            .errors
+            .push(format!("No _service file found in {}.", package.display()));
+        return report;
+    }
+    let services = match ServiceFile::read(&service_file) {
+        Ok(service_file) => service_file.services,
+        Err(err) => {
+            report.status = PackageStatus::Error;
+            report.errors.push(err.to_string());
+            return report;
+        }
+    };
+    let services: Vec<&Service> = services
+        .iter()
+        .filter(|service| service.name == SERVICE_NAME && service.runs_locally())
+        .collect();
+    if services.is_empty() {
+        report.status = PackageStatus::Error;
+        report
+            .errors
+            .push(format!("No {SERVICE_NAME} service found."));
+        return report;
+    }
+
+    let mut sources = SharedSources::new();
+    let mut changed = false;
+    let mut audit_failed = false;
+    let mut failed = false;
+    let mut lines: Vec<String> = Vec::new();
+    for service in services {
+        match update_service(service, package, args, &mut sources) {
+            Ok(update) => {
+                changed |= update.changed;
+                report.archives.extend(update.archive);
+                for line in update.lines {
+                    if !lines.contains(&line) {
+                        lines.push(line);
+                    }
+                }
+            }
+            Err(err) => {
+                if VulnerabilitiesFound::is_source_of(&err) {
+                    audit_failed = true;
+                } else {
+                    failed = true;
+                }
+                report.errors.push(err.to_string());
+            }
+        }
+    }
+    report.status = if failed {
+        PackageStatus::Error
+    } else if audit_failed {
+        PackageStatus::AuditFailed
+    } else if changed {
+        PackageStatus::Changed
+    } else {
+        PackageStatus::Unchanged
+    };
+    if changed && !lines.is_empty() {
+        report.changes_entry = Some(changes_entry(&args.message, &lines));
+    }
+    report
+}
+
+/// Update every package and report which ones changed. Committing and
+/// submitting the changes is left to the user.
+pub fn bulk_update(args: &BulkUpdateArgs) -> io::Result<()> {
+    info!("🚚 Updating {} packages.", args.packages.len());
+    let reports: Vec<PackageReport> = args
+        .packages
+        .iter()
+        .map(|package| update_package(package, args))
+        .collect();
+
+    info!("🚚 Bulk update summary:");
+    for report in &reports {
+        let package = report.package.display();
+        match report.status {
+            PackageStatus::Changed => info!("✅ {}: {}", package, report.status),
+            PackageStatus::Unchanged => info!("💤 {}: {}", package, report.status),
+            PackageStatus::AuditFailed | PackageStatus::Error => {
+                error!("🛑 {}: {}", package, report.status);
+                for err in &report.errors {
+                    error!("   {}", err);
+                }
+            }
+        }
+    }
+    for report in reports
+        .iter()
+        .filter(|report| report.status == PackageStatus::Changed)
+    {
+        let package = report.package.display();
+        info!("👉 Suggested steps for {}:", package);
+        if let Some(entry) = &report.changes_entry {
+            info!("👉 Add to the .changes file:\n{}", entry);
+        }
+        info!("👉 osc vc -m '{}' {}", args.message, package);
+        info!("👉 osc ci -m '{}' {}", args.message, package);
+        info!("👉 osc sr -m '{}' {}", args.message, package);
+    }
+
+    if let Some(path) = &args.report {
+        let content = serde_json::to_string_pretty(&reports).map_err(|err| {
+            error!(?err, "Failed to serialize the bulk update report.");
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?;
+        fs::write(path, content)?;
+        info!(?path, "🚚 Wrote the bulk update report.");
+    }
+
+    let failed = reports
+        .iter()
+        .filter(|report| {
+            matches!(
+                report.status,
+                PackageStatus::AuditFailed | PackageStatus::Error
+            )
+        })
+        .count();
+    if failed > 0 {
+        let msg = format!("{} of {} packages failed to update.", failed, reports.len());
+        error!(msg);
+        return Err(io::Error::other(msg));
+    }
+    Ok(())
+}
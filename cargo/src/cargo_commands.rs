@@ -7,18 +7,25 @@ use std::path::PathBuf;
 use tracing::{Level, debug, error, info, trace, warn};
 
 use crate::audit;
+use crate::crate_cache::CrateCache;
 // use crate::target::TARGET_TRIPLES;
 use crate::toml_manifest::has_dependencies;
 use crate::toml_manifest::is_workspace;
 use crate::toml_manifest::workspace_has_dependencies;
+use crate::upstream_hints::UpstreamHints;
 
 fn cargo_command(
     subcommand: &str,
     options: &[String],
     curdir: impl AsRef<Path>,
+    offline: bool,
 ) -> io::Result<String> {
-    let cmd = std::process::Command::new("cargo")
-        .arg(subcommand)
+    let mut cmd = std::process::Command::new("cargo");
+    cmd.arg(subcommand);
+    if offline {
+        cmd.arg("--offline");
+    }
+    let cmd = cmd
         .args(options.iter())
         .current_dir(curdir.as_ref())
         .output()?;
@@ -36,7 +43,13 @@ fn cargo_command(
     Ok(stdoutput.to_string())
 }
 
-pub fn cargo_fetch(curdir: &Path, manifest: &str, respect_lockfile: bool) -> io::Result<String> {
+pub fn cargo_fetch(
+    curdir: &Path,
+    manifest: &str,
+    respect_lockfile: bool,
+    crate_cache: Option<&Path>,
+    offline: bool,
+) -> io::Result<String> {
     info!("⤵️ Running `cargo fetch`...");
     let mut default_options: Vec<String> = vec![];
     let manifest_path = PathBuf::from(&manifest).canonicalize()?;
@@ -54,7 +67,7 @@ pub fn cargo_fetch(curdir: &Path, manifest: &str, respect_lockfile: bool) -> io:
         Ok(canonicalized_path_to_lockfile) => canonicalized_path_to_lockfile,
         Err(_) => {
             warn!("Lockfile not found in path... will attempt to regenerate");
-            cargo_generate_lockfile(&manifest_path_parent, manifest)?;
+            cargo_generate_lockfile(&manifest_path_parent, manifest, offline)?;
             manifest_path_parent.join("Cargo.lock").canonicalize()?
         }
     };
@@ -65,14 +78,25 @@ pub fn cargo_fetch(curdir: &Path, manifest: &str, respect_lockfile: bool) -> io:
         }
     } else {
         info!("🔓Attempting to regenerate lockfile...");
-        cargo_generate_lockfile(curdir, manifest)?;
+        cargo_generate_lockfile(curdir, manifest, offline)?;
         info!("🔒Regenerated lockfile.");
     }
     // TARGET_TRIPLES.iter().for_each(|target| {
     //     default_options.push("--target".to_string());
     //     default_options.push(target.to_string());
     // });
-    let res = cargo_command("fetch", &default_options, curdir);
+    let crate_cache = match (crate_cache, std::env::var_os("CARGO_HOME")) {
+        (Some(crate_cache), Some(cargo_home)) => {
+            let crate_cache = CrateCache::open(crate_cache)?;
+            crate_cache.seed(Path::new(&cargo_home), &possible_lockfile)?;
+            Some((crate_cache, PathBuf::from(cargo_home)))
+        }
+        _ => None,
+    };
+    let res = cargo_command("fetch", &default_options, curdir, offline);
+    if let (Ok(_), Some((crate_cache, cargo_home))) = (&res, &crate_cache) {
+        crate_cache.harvest(cargo_home, &possible_lockfile)?;
+    }
     res.inspect(|_| {
         info!("✅ `cargo fetch` finished!");
     })
@@ -91,12 +115,19 @@ pub fn cargo_vendor(
     update: bool,
     crates: &[String],
     respect_lockfile: bool,
+    crate_cache: Option<&Path>,
+    respect_source_config: bool,
+    upstream_hints: Option<&UpstreamHints>,
+    offline: bool,
 ) -> io::Result<Option<(PathBuf, String, bool)>> {
     let which_subcommand = if filter { "vendor-filterer" } else { "vendor" };
     let mut default_options: Vec<String> = vec![];
     if versioned_dirs {
         default_options.push("--versioned-dirs".to_string());
     }
+    if respect_source_config {
+        default_options.push("--respect-source-config".to_string());
+    }
     let mut first_manifest = custom_root.join("Cargo.toml");
     let mut lockfiles: Vec<PathBuf> = Vec::new();
     let mut global_has_deps = false;
@@ -135,7 +166,11 @@ pub fn cargo_vendor(
         Ok(canonicalized_path_to_lockfile) => canonicalized_path_to_lockfile,
         Err(_) => {
             warn!("Lockfile not found in path... will attempt to regenerate");
-            cargo_generate_lockfile(&first_manifest_parent, &first_manifest.to_string_lossy())?;
+            cargo_generate_lockfile(
+                &first_manifest_parent,
+                &first_manifest.to_string_lossy(),
+                offline,
+            )?;
             first_manifest_parent.join("Cargo.lock").canonicalize()?
         }
     };
@@ -213,13 +248,21 @@ pub fn cargo_vendor(
 				 false to true."
         );
         info!("🔓Attempting to regenerate lockfile...");
-        cargo_generate_lockfile(&first_manifest_parent, &first_manifest.to_string_lossy())?;
+        cargo_generate_lockfile(
+            &first_manifest_parent,
+            &first_manifest.to_string_lossy(),
+            offline,
+        )?;
         info!("🔒Regenerated lockfile.");
     }
 
     if filter {
         default_options.push("--platform=*-unknown-linux-gnu".to_string());
         default_options.push("--platform=wasm32-unknown-unknown".to_string());
+        let upstream_hints = upstream_hints.cloned().unwrap_or_default();
+        for platform in &upstream_hints.platforms {
+            default_options.push(format!("--platform={platform}"));
+        }
         // NOTE: by <https://github.com/msirringhaus>
         // We are conservative here and vendor all possible features, even
         // if they are not used in the spec. But we can't know.
@@ -227,7 +270,15 @@ pub fn cargo_vendor(
         // NOTE to that NOTE: by uncomfyhalomacro
         // I think we won't because we can't guess every feature they have.
         // It's usually enabled on `cargo build -F` tbh...
-        default_options.push("--all-features".to_string());
+        // Unless upstream tells which features matter.
+        if upstream_hints.features.is_empty() {
+            default_options.push("--all-features".to_string());
+        } else {
+            default_options.push(format!("--features={}", upstream_hints.features.join(",")));
+        }
+        if upstream_hints.dev_dependencies == Some(false) {
+            default_options.push("--keep-dep-kinds=no-dev".to_string());
+        }
     }
 
     if !update {
@@ -240,6 +291,7 @@ pub fn cargo_vendor(
         &first_manifest_parent,
         &first_manifest.to_string_lossy(),
         respect_lockfile,
+        offline,
     )?;
 
     info!("🚝 Attempting to fetch dependencies.");
@@ -247,15 +299,21 @@ pub fn cargo_vendor(
         &first_manifest_parent,
         &first_manifest.to_string_lossy(),
         respect_lockfile,
+        crate_cache,
+        offline,
     )?;
     info!("💼 Fetched dependencies.");
     info!("🏪 Running `cargo {}`...", &which_subcommand);
-    let res = cargo_command(which_subcommand, &default_options, first_manifest_parent);
+    let res = cargo_command(
+        which_subcommand,
+        &default_options,
+        first_manifest_parent,
+        offline,
+    );
 
     if possible_lockfile.is_file() {
         let lockfile_bytes = fs::read(&possible_lockfile)?;
-        hash.update(&lockfile_bytes);
-        let output_hash = hash.finalize();
+        let output_hash = blake3::hash(&lockfile_bytes);
         info!(?output_hash, "🔒 Lockfile hash after: ");
         info!(?possible_lockfile, "🔓 Adding lockfile.");
         lockfiles.push(possible_lockfile.as_path().to_path_buf());
@@ -263,9 +321,8 @@ pub fn cargo_vendor(
 
     info!("🛡️🫥 Auditing lockfiles...");
     if let Ok(audit_result) = audit::perform_cargo_audit(&lockfiles, i_accept_the_risk) {
-        audit::process_reports(audit_result).map_err(|err| {
+        audit::process_reports(audit_result).inspect_err(|err| {
             error!(?err);
-            io::Error::new(io::ErrorKind::Interrupted, err.to_string())
         })?;
     }
     info!("🛡️🙂 All lockfiles are audited");
@@ -293,7 +350,22 @@ pub fn cargo_vendor(
     }
 }
 
-pub fn cargo_generate_lockfile(curdir: &Path, manifest: &str) -> io::Result<String> {
+/// Resolve the dependencies of a manifest with all features, without the
+/// network and without touching its lockfile. Fails if the lockfile does not
+/// satisfy the manifest or a crate is not available locally.
+pub fn cargo_metadata_offline(manifest: &Path) -> io::Result<String> {
+    let curdir = manifest.parent().unwrap_or(manifest);
+    let default_options = vec![
+        "--format-version=1".to_string(),
+        "--all-features".to_string(),
+        "--locked".to_string(),
+        "--manifest-path".to_string(),
+        manifest.to_string_lossy().to_string(),
+    ];
+    cargo_command("metadata", &default_options, curdir, true)
+}
+
+pub fn cargo_generate_lockfile(curdir: &Path, manifest: &str, offline: bool) -> io::Result<String> {
     info!("🔓 💂 Running `cargo generate-lockfile`...");
     let mut original_hasher = blake3::Hasher::new();
     let mut regenerated_hasher = blake3::Hasher::new();
@@ -313,7 +385,7 @@ pub fn cargo_generate_lockfile(curdir: &Path, manifest: &str) -> io::Result<Stri
         default_options.push("--manifest-path".to_string());
         default_options.push(manifest.to_string());
     }
-    let res = cargo_command("generate-lockfile", &default_options, curdir);
+    let res = cargo_command("generate-lockfile", &default_options, curdir, offline);
     if possible_lockfile.exists() {
         let lockfile_bytes = fs::read(&possible_lockfile)?;
         regenerated_hasher.update(&lockfile_bytes);
@@ -339,6 +411,7 @@ pub fn cargo_update(
     curdir: &Path,
     manifest: &str,
     respect_lockfile: bool,
+    offline: bool,
 ) -> io::Result<String> {
     let mut default_options: Vec<String> = vec![];
     if global_update {
@@ -350,7 +423,7 @@ pub fn cargo_update(
             Ok(canonicalized_path_to_lockfile) => canonicalized_path_to_lockfile,
             Err(_) => {
                 warn!("Lockfile not found in path... will attempt to regenerate");
-                cargo_generate_lockfile(manifest_path_parent, manifest)?;
+                cargo_generate_lockfile(manifest_path_parent, manifest, offline)?;
                 manifest_path_parent.join("Cargo.lock").canonicalize()?
             }
         };
@@ -364,7 +437,7 @@ pub fn cargo_update(
             default_options.push("--locked".to_string());
         }
 
-        cargo_command("update", &default_options, curdir)
+        cargo_command("update", &default_options, curdir, offline)
             .inspect(|_| {
                 info!("✅ Updated dependencies.");
             })
@@ -550,7 +623,7 @@ pub fn cargo_update(
                     }
                 }
             }
-            cargo_command("update", &default_options, new_cur_dir)
+            cargo_command("update", &default_options, new_cur_dir, offline)
                 .inspect(|_| {
                     info!("✅ Updated dependencies for crate.");
                 })
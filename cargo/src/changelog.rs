@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use hifitime::efmt::Format;
+use hifitime::efmt::Formatter;
+use hifitime::prelude::*;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::Opts;
+use crate::dependency_changes::DependencyChanges;
+use crate::dependency_changes::compare_lockfiles;
+
+// NOTE: Same header as the entries Roast SCM generates.
+pub const CHANGELOG_LONG_SET_OF_DASHES: &str =
+    "-------------------------------------------------------------------";
+pub const CHANGELOG_DATE_TIME_FORMAT: &str = "%a %b %d %H:%M:%S %T %Y";
+
+/// Copies of the lockfiles taken before cargo updates them.
+#[derive(Debug)]
+pub struct LockfileSnapshot {
+    tempdir: tempfile::TempDir,
+    lockfiles: Vec<(PathBuf, PathBuf, blake3::Hash)>,
+}
+
+impl LockfileSnapshot {
+    /// Snapshot every given lockfile that exists. Missing ones are generated
+    /// by cargo later and have nothing to compare with.
+    pub fn take(lockfiles: &[PathBuf]) -> io::Result<Self> {
+        let tempdir = tempfile::Builder::new()
+            .prefix(".lockfile-snapshot")
+            .rand_bytes(12)
+            .tempdir()?;
+        let mut snapshot = Vec::new();
+        for (index, lockfile) in lockfiles.iter().enumerate() {
+            if !lockfile.is_file() {
+                info!(
+                    ?lockfile,
+                    "🗒️ Lockfile does not exist yet. It is left out of the changelog."
+                );
+                continue;
+            }
+            let bytes = fs::read(lockfile)?;
+            let copy = tempdir.path().join(index.to_string()).join("Cargo.lock");
+            fs::create_dir_all(copy.parent().unwrap_or(tempdir.path()))?;
+            fs::write(&copy, &bytes)?;
+            snapshot.push((lockfile.clone(), copy, blake3::hash(&bytes)));
+        }
+        debug!(?snapshot);
+        Ok(LockfileSnapshot {
+            tempdir,
+            lockfiles: snapshot,
+        })
+    }
+
+    pub fn is_unchanged(&self) -> io::Result<bool> {
+        for (lockfile, _, hash) in &self.lockfiles {
+            if blake3::hash(&fs::read(lockfile)?) != *hash {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn changes(&self, i_accept_the_risk: &[String]) -> io::Result<DependencyChanges> {
+        debug!(snapshot = ?self.tempdir.path());
+        let previous: Vec<&PathBuf> = self.lockfiles.iter().map(|(_, copy, _)| copy).collect();
+        let current: Vec<&PathBuf> = self
+            .lockfiles
+            .iter()
+            .map(|(lockfile, _, _)| lockfile)
+            .collect();
+        compare_lockfiles(&previous, &current, i_accept_the_risk)
+    }
+}
+
+/// Lines of a changes entry describing the dependency changes.
+pub fn changes_entry_lines(changes: &DependencyChanges) -> Vec<String> {
+    let mut lines = Vec::new();
+    for advisory in &changes.fixed_advisories {
+        lines.push(format!(
+            "* Fixes {} in {} {}: {}",
+            advisory.id, advisory.name, advisory.version, advisory.title
+        ));
+    }
+    for change in &changes.upgraded {
+        lines.push(format!(
+            "* {} {} -> {}",
+            change.name, change.from, change.to
+        ));
+    }
+    for change in &changes.downgraded {
+        lines.push(format!(
+            "* {} {} -> {} (downgrade)",
+            change.name, change.from, change.to
+        ));
+    }
+    for change in &changes.added {
+        lines.push(format!("* Added {} {}", change.name, change.version));
+    }
+    for change in &changes.removed {
+        lines.push(format!("* Removed {} {}", change.name, change.version));
+    }
+    for change in &changes.source_changed {
+        lines.push(format!(
+            "* {} {} now comes from {}",
+            change.name,
+            change.version,
+            change.to.as_deref().unwrap_or("a local path")
+        ));
+    }
+    lines
+}
+
+/// First line of the changes entries about dependency updates.
+pub const CHANGES_ENTRY_TITLE: &str = "Update vendored dependencies";
+
+/// A changes entry with the given title and lines, without the header.
+pub fn changes_entry(title: &str, lines: &[String]) -> String {
+    let mut entry = format!("- {title}:\n");
+    for line in lines {
+        entry.push_str(&format!("  {line}\n"));
+    }
+    entry
+}
+
+/// The changes file to write to. Without `changesoutfile`, this is the only
+/// `.changes` file of the current directory, i.e. the package.
+fn changes_file(opts: &Opts) -> io::Result<PathBuf> {
+    if let Some(changesoutfile) = &opts.changesoutfile {
+        return Ok(changesoutfile.clone());
+    }
+    let mut candidates = Vec::new();
+    for entry in fs::read_dir(std::env::current_dir()?)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "changes")
+        {
+            candidates.push(path);
+        }
+    }
+    match candidates.as_slice() {
+        [changes_file] => Ok(changes_file.clone()),
+        _ => {
+            let msg = format!(
+                "Expected exactly one `.changes` file in the current directory but found {}. Set `changesoutfile`.",
+                candidates.len()
+            );
+            error!(msg);
+            Err(io::Error::new(io::ErrorKind::NotFound, msg))
+        }
+    }
+}
+
+/// Where the dependency changes entry is written and who signs it. Resolved
+/// before vendoring so a missing author or changes file fails before any
+/// output is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangesDestination {
+    pub file: PathBuf,
+    pub author: String,
+}
+
+impl ChangesDestination {
+    pub fn from_opts(opts: &Opts) -> io::Result<Self> {
+        let changesauthor = opts.changesauthor.as_deref().ok_or_else(|| {
+            let msg = "No changes author provided.";
+            error!(msg);
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })?;
+        let author = match &opts.changesemail {
+            Some(changesemail) => format!("{changesauthor} <{changesemail}>"),
+            None => changesauthor.to_string(),
+        };
+        Ok(Self {
+            file: changes_file(opts)?,
+            author,
+        })
+    }
+
+    fn header(&self) -> io::Result<String> {
+        let time_format = Format::from_str(CHANGELOG_DATE_TIME_FORMAT).map_err(|err| {
+            error!(?err);
+            io::Error::other(err)
+        })?;
+        let time_now = Epoch::now().map_err(|err| {
+            error!(?err);
+            io::Error::other(err)
+        })?;
+        let formatted_time_now = Formatter::new(time_now, time_format);
+        Ok(format!(
+            "{CHANGELOG_LONG_SET_OF_DASHES}\n{formatted_time_now} - {}",
+            self.author
+        ))
+    }
+}
+
+/// Prepend a dated entry listing the dependency changes since the snapshot to
+/// the changes file. Nothing is written if no lockfile changed.
+pub fn write_changes_entry(
+    snapshot: &LockfileSnapshot,
+    destination: &ChangesDestination,
+    opts: &Opts,
+) -> io::Result<()> {
+    if snapshot.is_unchanged()? {
+        info!("🗒️ Lockfiles did not change. No changelog entry needed.");
+        return Ok(());
+    }
+    let changes = snapshot.changes(&opts.i_accept_the_risk)?;
+    let lines = changes_entry_lines(&changes);
+    if lines.is_empty() {
+        info!("🗒️ No dependency changes. No changelog entry needed.");
+        return Ok(());
+    }
+    let changes_file = &destination.file;
+    let existing = if changes_file.exists() {
+        fs::read_to_string(changes_file)?
+    } else {
+        String::new()
+    };
+    let mut entry = changes_entry(CHANGES_ENTRY_TITLE, &lines);
+    entry.push('\n');
+    let mut content = format!("{}\n\n{}{}", destination.header()?, entry, existing);
+    content = content.trim_end().to_string();
+    content.push('\n');
+    fs::write(changes_file, &content)?;
+    info!(
+        "🗒️ Successfully added dependency changes to `{}`.",
+        changes_file.display()
+    );
+    Ok(())
+}
@@ -9,27 +9,68 @@
 use std::io;
 use std::path::{Path, PathBuf};
 
+use crate::bulk_update::bulk_update;
+use crate::changelog::CHANGES_ENTRY_TITLE;
+use crate::changelog::ChangesDestination;
+use crate::changelog::LockfileSnapshot;
+use crate::changelog::write_changes_entry;
 use crate::consts::VENDOR_PATH_PREFIX;
+use crate::offline::check_local_source_supported;
+use crate::plan::dry_run;
+use crate::policy::Policy;
+use crate::preflight::check_path_dependencies;
 use crate::registry::run_cargo_vendor_home_registry;
+use crate::service_definition::INSTALLED_SERVICE_DEFINITION;
+use crate::service_definition::run_service_definition;
+use crate::service_file::run_service_file;
+use crate::staleness::check_staleness;
+use crate::upstream_config::apply_upstream_audit_config;
+use crate::upstream_hints::UpstreamHints;
+use crate::upstream_hints::apply_upstream_hints;
 use crate::vendor::run_cargo_vendor;
+use crate::verify::seed_lockfiles;
+use crate::verify::verify_against;
 use libroast::common::Compression;
 
-use clap::{Args, Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use libroast::operations::cli::{RawArgs, RoastScmArgs};
 use libroast::operations::raw::raw_opts;
 use libroast::utils::copy_dir_all;
 use libroast::{decompress, utils};
+use serde::{Deserialize, Serialize};
 
 #[allow(unused_imports)]
 use tracing::{Level, debug, error, info, trace, warn};
 
-#[derive(Debug, Clone, ValueEnum, Default)]
+#[derive(Debug, Clone, ValueEnum, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Method {
     Registry,
     #[default]
     Vendor,
 }
 
+/// How the registry method lays out the tarball.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistryLayout {
+    /// A snapshot of `CARGO_HOME` that builds need to set `CARGO_HOME` to.
+    #[default]
+    CargoHome,
+    /// A cargo local registry with a `.cargo/config.toml` replacing crates.io.
+    LocalRegistry,
+}
+
+/// How a policy check reacts to a finding, following cargo-deny's lint levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintLevel {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
 #[derive(Parser, Debug)]
 #[command(
     author,
@@ -39,7 +80,8 @@ pub enum Method {
     after_long_help = "Set verbosity and tracing through `RUST_LOG` environmental variable e.g. `RUST_LOG=trace`
 
 Bugs can be reported on GitHub: https://github.com/openSUSE/obs-service-cargo_vendor/issues",
-    max_term_width = 120
+    max_term_width = 120,
+    subcommand_negates_reqs = true
 )]
 pub struct Opts {
     #[arg(long, action = clap::ArgAction::Set, default_value_t = false, help = "Whether to generate or update a changelog file or not. To be passed to Roast SCM.")]
@@ -47,7 +89,7 @@ pub struct Opts {
     #[arg(
         long,
         short = 'A',
-        required_if_eq("changesgenerate", "true"),
+        required_if_eq_any([("changesgenerate", "true"), ("changesdependencies", "true")]),
         help = "Author to include during the changelog generation. To be passed to Roast SCM."
     )]
     pub changesauthor: Option<String>,
@@ -67,6 +109,13 @@ pub struct Opts {
                 newest changes to the top-most part of the text file. To be passed to Roast SCM."
     )]
     pub changesoutfile: Option<PathBuf>,
+    #[arg(
+        long,
+        action = clap::ArgAction::Set,
+        default_value_t = false,
+        help = "Whether to add an entry listing the updated, added and removed crates and the fixed advisories to the changes file if the lockfiles changed. Uses `changesauthor`, `changesemail` and `changesoutfile`. Without `changesoutfile`, the only `.changes` file of the current directory is used."
+    )]
+    pub changesdependencies: bool,
     #[arg(
         long,
         help = "Whether to hard code the version or not. Set it to hard code one, otherwise, it \
@@ -112,6 +161,17 @@ pub struct Opts {
     pub method: Method,
     #[arg(
         long,
+        value_enum,
+        default_value_t,
+        help = "Available only if `--method` is set to registry. `cargo-home` keeps a snapshot of `CARGO_HOME`. `local-registry` turns the crates of the lockfiles into a cargo local registry with a `.cargo/config.toml` replacing crates.io, so builds need no `CARGO_HOME`."
+    )]
+    pub registry_layout: RegistryLayout,
+    #[arg(
+        long,
+        required = false,
+        required_unless_present = "from_service",
+        default_value = "",
+        hide_default_value = true,
         visible_aliases = ["srctar", "srcdir", "target", "url"],
         help = "Where to find sources. Source is either a directory or a source tarball or a URL to a remote git repository."
     )]
@@ -146,6 +206,10 @@ pub struct Opts {
     pub update: bool,
     #[arg(
         long,
+        required = false,
+        required_unless_present = "from_service",
+        default_value = ".",
+        hide_default_value = true,
         help = "Where to output vendor.tar* and cargo_config if method is vendor and registry.tar* if method is registry. If using with `osc service`, this option is automatically appended."
     )]
     pub outdir: PathBuf,
@@ -165,6 +229,55 @@ pub struct Opts {
         default_value_t = false
     )]
     pub respect_lockfile: bool,
+    #[arg(
+        long,
+        action = clap::ArgAction::Set,
+        default_value_t = false,
+        help = "Keep the existing tarball in `outdir` or the current directory if its archive manifest records the same method, lockfiles and options. The lockfiles are compared after the update. The sources and the version of obs-service-cargo are not compared. Only archiving is skipped, the dependencies are still vendored, audited and checked against the policy."
+    )]
+    pub skip_unchanged: bool,
+    #[arg(
+        long,
+        action = clap::ArgAction::Set,
+        default_value_t = false,
+        help = "Extract the sources and show which manifests and lockfiles would be used, how dependencies would be updated and which tarball would be written, without running cargo. Fails if the configuration has problems such as a missing manifest."
+    )]
+    pub dry_run: bool,
+    #[arg(
+        long,
+        action = clap::ArgAction::Set,
+        default_value_t = false,
+        conflicts_with_all = ["dry_run", "verify_against"],
+        help = "Check without the network whether the existing tarball in `outdir` or the current directory is stale, e.g. for buildtime services or in CI. Fails if the lockfiles of the sources are not satisfied by the ones of the tarball or cargo can't resolve the manifests with the tarball."
+    )]
+    pub check: bool,
+    #[arg(
+        long,
+        help = "Directory of downloaded crates shared between runs, e.g. for bulk updates. Crates are keyed by their checksum and verified before they are used. Only crates of the lockfiles are taken from it."
+    )]
+    pub crate_cache: Option<PathBuf>,
+    #[arg(
+        long,
+        action = clap::ArgAction::Set,
+        default_value_t = false,
+        help = "Never use the network. Crates are taken from `local-registry` or, if not set, from `crate-cache`. Crates missing from there are reported before cargo runs. Cargo runs with `--offline`. Only supported by the vendor method without `filter`."
+    )]
+    pub offline: bool,
+    #[arg(
+        long,
+        help = "Replace crates.io with a local source: a cargo local registry, a directory of unpacked crates such as a vendor directory, or a directory of `.crate` files. Only supported by the vendor method without `filter`."
+    )]
+    pub local_registry: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Alternate registry that dependencies refer to with `registry = \"name\"`, as `name=index`. The index is a URL or the path to a local git repository of a registry index. Can be given multiple times."
+    )]
+    pub alternate_registry: Vec<String>,
+    #[arg(
+        long,
+        help = "Existing vendor or registry tarball of the same method to merge the new one into, e.g. of another tag of a multi-tag project. Crates of both are deduplicated and both sets of lockfiles are kept. Looked up in `outdir` first."
+    )]
+    pub merge_into: Option<PathBuf>,
     #[arg(
         long,
         help = "A list of rustsec-id's to ignore. By setting this value, you acknowledge that this issue does not affect your package and you should be exempt from resolving it."
@@ -175,8 +288,163 @@ pub struct Opts {
         help = "Set of specific crates to update. If not empty, it will set the global update flag to false. You can specify a valid version string by adding a `@` after the crate name e.g. `foo@1.2.3`. You can also do recursive updates of a crate by appending `recursive` to `@` e.g. `foo@recursive`. However, recursive can't be used with precise. You can specify a manifest path to update a package with `+` e.g. `foo@1.0+foo/better/Cargo.toml`. See `cargo help update` for info about how to update specific crates."
     )]
     pub update_crate: Vec<String>,
+    #[arg(
+        long,
+        action = clap::ArgAction::Set,
+        default_value_t = false,
+        help = "Fail if a git dependency follows a branch or the default branch instead of a pinned `rev` or `tag`."
+    )]
+    pub deny_floating_git_refs: bool,
+    #[arg(
+        long,
+        help = "Hosts that git dependencies are allowed to be fetched from e.g. `github.com`. If this and `--allowed-git` are empty, git dependencies from anywhere are allowed."
+    )]
+    pub allowed_git_host: Vec<String>,
+    #[arg(
+        long,
+        help = "Registries that crates are allowed to come from, either as an index URL or `crates-io`. Defaults to `crates-io` only. If set, crates.io must be listed as well."
+    )]
+    pub allowed_registry: Vec<String>,
+    #[arg(
+        long,
+        help = "URL prefixes of git repositories that crates are allowed to come from e.g. `https://github.com/rust-lang/`. If this and `--allowed-git-host` are empty, git dependencies from anywhere are allowed."
+    )]
+    pub allowed_git: Vec<String>,
+    #[arg(
+        long,
+        help = "Path to a policy file. Its `[sources]` and `[bans]` sections follow cargo-deny's `deny.toml`. Values are merged with the policy parameters."
+    )]
+    pub policy: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Crates that must not be part of the dependency tree, either as a name or with a version requirement e.g. `openssl` or `time@<0.2.23`."
+    )]
+    pub deny_crate: Vec<String>,
+    #[arg(
+        long,
+        value_enum,
+        help = "Whether crates resolved at multiple versions across the lockfiles are allowed, warned about or denied. Overrides `multiple-versions` of the policy file, which defaults to `warn`."
+    )]
+    pub multiple_versions: Option<LintLevel>,
+    #[arg(
+        long,
+        help = "Where to write the results of the policy checks as JSON. Nothing is written if not set."
+    )]
+    pub policy_report: Option<PathBuf>,
+    #[arg(
+        long,
+        action = clap::ArgAction::Set,
+        default_value_t = false,
+        help = "Apply the ignored advisories and bans of upstream's `deny.toml` and `.cargo/audit.toml`. Ignored advisories are merged with `i-accept-the-risk`. Upstream configuration is always shown but not applied by default. Allowed sources of upstream are never applied."
+    )]
+    pub trust_upstream_audit_config: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Whether crates that are not vetted by the cargo-vet audits of the sources or `--vet-audits` are ignored, warned about or denied. Violations are always denied."
+    )]
+    pub cargo_vet: LintLevel,
+    #[arg(
+        long,
+        help = "Audit files in the format of cargo-vet's `audits.toml`, e.g. maintained by the distribution. Used in addition to the cargo-vet store of the sources."
+    )]
+    pub vet_audits: Vec<PathBuf>,
+    #[arg(
+        long,
+        help = "Criteria every crate has to be vetted for. Defaults to the `dependency-criteria` and `default-criteria` of the cargo-vet config, or `safe-to-deploy`."
+    )]
+    pub vet_criteria: Option<String>,
+    #[arg(
+        long,
+        help = "Previous vendor or registry tarball to compare the dependencies with. By default, a tarball with the name of the new one is looked up in `outdir` and then in the current directory."
+    )]
+    pub previous_archive: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Where to write the dependency changes since the previous tarball as JSON. Nothing is written if not set."
+    )]
+    pub dependency_report: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Vendor again with `update` set to false and `respect-lockfile` set to true, then compare the result with this tarball file by file instead of writing to `outdir`. Fails on any difference of paths, modes or contents."
+    )]
+    pub verify_against: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "verify_against",
+        help = "Run every `cargo_vendor` service of a `_service` file in order, with the parameters translated to options like `osc` does. Other services are skipped. Sources are extracted once and shared between the services. Run it in the directory of the `_service` file, like `osc service manualrun`. `outdir` defaults to the current directory."
+    )]
+    pub from_service: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "TOML file with settings instead of parameters, e.g. a list of manifests with the crates to update in each. Parameters override its settings. Defaults to `_cargo_vendor.toml` in the current directory if it exists."
+    )]
+    pub config: Option<PathBuf>,
+    #[arg(skip)]
+    pub verify_lockfiles_from: Option<PathBuf>,
+    #[arg(skip)]
+    pub extracted_src: Option<PathBuf>,
+    #[arg(skip)]
+    pub upstream_policy: Option<Policy>,
+    #[arg(skip)]
+    pub upstream_hints: Option<UpstreamHints>,
     #[clap(flatten)]
     pub vendor_specific_args: Option<VendorArgs>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Vendor the checked out OBS packages again and report which ones changed.
+    BulkUpdate(BulkUpdateArgs),
+    /// Print the `cargo_vendor.service` file generated from the options, or
+    /// check an existing one.
+    #[command(hide = true)]
+    ServiceDefinition(ServiceDefinitionArgs),
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ServiceDefinitionArgs {
+    #[arg(
+        long,
+        conflicts_with = "check",
+        help = "Where to write the service definition. Printed if not set."
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = INSTALLED_SERVICE_DEFINITION,
+        help = "Fail if the service definition disagrees with the options of this binary."
+    )]
+    pub check: Option<PathBuf>,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct BulkUpdateArgs {
+    #[arg(
+        required = true,
+        help = "Directories of checked out OBS packages with a `_service` file."
+    )]
+    pub packages: Vec<PathBuf>,
+    #[arg(
+        long,
+        default_value = CHANGES_ENTRY_TITLE,
+        help = "Title of the suggested changes entry and message of the suggested `osc` commands."
+    )]
+    pub message: String,
+    #[arg(
+        long,
+        help = "Directory of downloaded crates shared between the packages. Used by services that don't set `crate-cache` themselves."
+    )]
+    pub crate_cache: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Where to write the report of all packages as JSON. Nothing is written if not set."
+    )]
+    pub report: Option<PathBuf>,
 }
 
 #[derive(Debug, Args, Clone)]
@@ -227,8 +495,59 @@ pub fn decompress(comp_type: &Compression, outdir: &Path, src: &Path) -> io::Res
 }
 
 impl Opts {
+    /// The root manifest and the extra manifest paths relative to the custom root.
+    pub fn manifests(&self, custom_root: &Path) -> Vec<PathBuf> {
+        let mut manifests = vec![custom_root.join("Cargo.toml")];
+        manifests.extend(
+            self.manifest_path
+                .iter()
+                .map(|manifest| custom_root.join(manifest)),
+        );
+        manifests
+    }
+
+    /// File name of the archive written to `outdir`, e.g. `vendor-tag.tar.zst`
+    /// or `registry.tar.gz`, for the given compression.
+    pub fn archive_name(&self, compression: &Compression) -> io::Result<PathBuf> {
+        let prefix = match self.method {
+            Method::Registry => "registry",
+            Method::Vendor => "vendor",
+        };
+        let outfile = match &self.tag {
+            Some(v) => format!("{prefix}-{v}"),
+            None => prefix.to_string(),
+        };
+        let mut outfile = PathBuf::from(outfile);
+        let extension = match compression {
+            Compression::Gz => "tar.gz",
+            Compression::Xz => "tar.xz",
+            Compression::Zst | Compression::Zstd => "tar.zst",
+            Compression::Bz2 => "tar.bz",
+            Compression::Not => "tar",
+        };
+
+        if !outfile.set_extension(extension) {
+            return Err(io::Error::other("Unable to set extension"));
+        }
+        Ok(outfile)
+    }
+
     pub fn run_vendor(&mut self) -> io::Result<()> {
         debug!(?self);
+        match &self.command {
+            Some(Command::BulkUpdate(args)) => return bulk_update(args),
+            Some(Command::ServiceDefinition(args)) => return run_service_definition(args),
+            None => {}
+        }
+        if let Some(service_file) = self.from_service.clone() {
+            return run_service_file(&service_file, self);
+        }
+        if let Some(expected) = self.verify_against.clone() {
+            return verify_against(self, &expected);
+        }
+        // NOTE: Fail early on an invalid policy instead of after vendoring.
+        Policy::from_opts(self)?;
+        check_local_source_supported(self)?;
         let is_url = url::Url::parse(&self.src).is_ok();
         let tempdir_for_workdir = tempfile::Builder::new()
             .prefix(VENDOR_PATH_PREFIX)
@@ -253,7 +572,9 @@ impl Opts {
             std::path::Path::new(&self.src).to_path_buf()
         };
 
-        if target.is_dir() {
+        if let Some(extracted) = &self.extracted_src {
+            copy_dir_all(extracted, &workdir)?;
+        } else if target.is_dir() {
             copy_dir_all(&target, &workdir)?;
         } else if target.is_file() && utils::is_supported_format(&target).is_ok() {
             let raw_args = RawArgs {
@@ -267,7 +588,7 @@ impl Opts {
             if let Some(revision) = &self.revision {
                 let roast_scm_args = RoastScmArgs {
                     silent: false,
-                    changesgenerate: self.changesgenerate,
+                    changesgenerate: self.changesgenerate && !self.dry_run,
                     changesauthor: self.changesauthor.clone(),
                     changesemail: self.changesemail.clone(),
                     changesoutfile: self.changesoutfile.clone(),
@@ -363,6 +684,33 @@ impl Opts {
         }
 
         if setup_workdir.exists() && setup_workdir.is_dir() {
+            if let Some(extracted) = &self.verify_lockfiles_from {
+                seed_lockfiles(extracted, &setup_workdir)?;
+            }
+            apply_upstream_hints(&custom_root, self)?;
+            check_path_dependencies(&setup_workdir, &custom_root, &self.manifests(&custom_root))?;
+            if self.dry_run {
+                dry_run(&setup_workdir, &custom_root, self)?;
+                tempdir_for_workdir.close()?;
+                return Ok(());
+            }
+            if self.check {
+                check_staleness(&setup_workdir, &custom_root, self)?;
+                tempdir_for_workdir.close()?;
+                return Ok(());
+            }
+            apply_upstream_audit_config(&setup_workdir, &custom_root, self)?;
+            let snapshot = if self.changesdependencies {
+                let destination = ChangesDestination::from_opts(self)?;
+                let lockfiles: Vec<PathBuf> = self
+                    .manifests(&custom_root)
+                    .iter()
+                    .map(|manifest| manifest.with_file_name("Cargo.lock"))
+                    .collect();
+                Some((LockfileSnapshot::take(&lockfiles)?, destination))
+            } else {
+                None
+            };
             match &self.method {
                 Method::Registry => {
                     run_cargo_vendor_home_registry(&setup_workdir, &custom_root, self)
@@ -386,6 +734,9 @@ osc service -vvv mr cargo_vendor
                     _ => error!(?err)
                 }
             })?;
+            if let Some((snapshot, destination)) = &snapshot {
+                write_changes_entry(snapshot, destination, self)?;
+            }
         } else {
             let mut msg: String =
                 "It seems that the setup workdir is not a directory or does not exist.".to_string();
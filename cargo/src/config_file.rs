@@ -0,0 +1,378 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use clap::CommandFactory;
+use clap::FromArgMatches;
+use clap::ValueEnum;
+use clap::parser::ValueSource;
+use libroast::common::Compression;
+use serde::Deserialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::LintLevel;
+use crate::cli::Method;
+use crate::cli::Opts;
+use crate::cli::RegistryLayout;
+use crate::cli::VendorArgs;
+
+/// Name of the configuration file looked up in the package directory.
+pub const CONFIG_FILE_NAME: &str = "_cargo_vendor.toml";
+
+/// A manifest besides the root manifest and the crates to update in it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ManifestConfig {
+    /// Path of the manifest relative to the root of the sources.
+    pub path: PathBuf,
+    /// Crates to update in this manifest, in the format of `update-crate`
+    /// without the `+manifest` part e.g. `foo@1.2.3` or `foo@recursive`.
+    #[serde(default)]
+    pub update_crates: Vec<String>,
+}
+
+/// A RUSTSEC advisory accepted for this package and why.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AcceptedRisk {
+    pub id: String,
+    pub reason: String,
+}
+
+/// Settings of `_cargo_vendor.toml`. They are the same as the parameters,
+/// with tables where a parameter is a list of encoded strings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    pub method: Option<Method>,
+    pub registry_layout: Option<RegistryLayout>,
+    pub compression: Option<String>,
+    pub tag: Option<String>,
+    pub custom_root: Option<String>,
+    pub no_root_manifest: Option<bool>,
+    pub update: Option<bool>,
+    pub respect_lockfile: Option<bool>,
+    pub versioned_dirs: Option<bool>,
+    pub filter: Option<bool>,
+    pub skip_unchanged: Option<bool>,
+    pub offline: Option<bool>,
+    pub crate_cache: Option<PathBuf>,
+    pub local_registry: Option<PathBuf>,
+    /// Index of each alternate registry by name.
+    #[serde(default)]
+    pub alternate_registries: BTreeMap<String, String>,
+    /// Crates to update in every manifest.
+    #[serde(default)]
+    pub update_crates: Vec<String>,
+    #[serde(default, rename = "manifest")]
+    pub manifests: Vec<ManifestConfig>,
+    #[serde(default, rename = "accepted-risk")]
+    pub accepted_risks: Vec<AcceptedRisk>,
+    pub deny_floating_git_refs: Option<bool>,
+    #[serde(default)]
+    pub allowed_git_hosts: Vec<String>,
+    #[serde(default)]
+    pub allowed_registries: Vec<String>,
+    #[serde(default)]
+    pub allowed_git: Vec<String>,
+    pub policy: Option<PathBuf>,
+    #[serde(default)]
+    pub deny_crates: Vec<String>,
+    pub multiple_versions: Option<LintLevel>,
+    pub trust_upstream_audit_config: Option<bool>,
+    pub cargo_vet: Option<LintLevel>,
+    #[serde(default)]
+    pub vet_audits: Vec<PathBuf>,
+    pub vet_criteria: Option<String>,
+}
+
+fn invalid(path: &Path, msg: &str) -> io::Error {
+    let msg = format!("{}: {}", path.display(), msg);
+    error!(msg);
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn is_rustsec_id(id: &str) -> bool {
+    let mut parts = id.split('-');
+    parts.next() == Some("RUSTSEC")
+        && parts
+            .next()
+            .is_some_and(|year| year.len() == 4 && year.chars().all(|c| c.is_ascii_digit()))
+        && parts
+            .next()
+            .is_some_and(|number| number.len() == 4 && number.chars().all(|c| c.is_ascii_digit()))
+        && parts.next().is_none()
+}
+
+impl ConfigFile {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let config: ConfigFile =
+            toml::from_str(&content).map_err(|err| invalid(path, &err.to_string()))?;
+        config.validate(path)?;
+        Ok(config)
+    }
+
+    /// Check what deserializing can't, naming the offending entry.
+    fn validate(&self, path: &Path) -> io::Result<()> {
+        if let Some(compression) = &self.compression
+            && Compression::from_str(compression, true).is_err()
+        {
+            return Err(invalid(
+                path,
+                &format!("`compression`: unknown compression `{compression}`."),
+            ));
+        }
+        for (index, risk) in self.accepted_risks.iter().enumerate() {
+            if !is_rustsec_id(&risk.id) {
+                return Err(invalid(
+                    path,
+                    &format!(
+                        "`accepted-risk[{index}].id`: `{}` is not a RUSTSEC advisory id like `RUSTSEC-2023-0044`.",
+                        risk.id
+                    ),
+                ));
+            }
+            if risk.reason.trim().is_empty() {
+                return Err(invalid(
+                    path,
+                    &format!(
+                        "`accepted-risk[{index}].reason`: the reason for accepting {} is empty.",
+                        risk.id
+                    ),
+                ));
+            }
+        }
+        for (index, manifest) in self.manifests.iter().enumerate() {
+            if manifest
+                .path
+                .file_name()
+                .is_none_or(|name| name != "Cargo.toml")
+            {
+                return Err(invalid(
+                    path,
+                    &format!(
+                        "`manifest[{index}].path`: `{}` is not a `Cargo.toml`.",
+                        manifest.path.display()
+                    ),
+                ));
+            }
+            if let Some(spec) = manifest
+                .update_crates
+                .iter()
+                .find(|spec| spec.contains('+'))
+            {
+                return Err(invalid(
+                    path,
+                    &format!(
+                        "`manifest[{index}].update-crates`: `{spec}` must not name a manifest, it is updated in `{}`.",
+                        manifest.path.display()
+                    ),
+                ));
+            }
+        }
+        for (name, index) in &self.alternate_registries {
+            if index.trim().is_empty() {
+                return Err(invalid(
+                    path,
+                    &format!("`alternate-registries.{name}`: the index is empty."),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether an argument was given on the command line, which for a service
+/// means as a parameter of the `_service` file.
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.value_source(id), Some(ValueSource::CommandLine))
+}
+
+/// Fill in the settings of the configuration file that are not given as
+/// arguments. Relative paths of the file are relative to its directory, except
+/// for manifests, which are relative to the sources.
+pub fn apply_config_file(opts: &mut Opts, matches: &ArgMatches, dir: &Path) -> io::Result<()> {
+    let path = match &opts.config {
+        Some(config) => {
+            let path = dir.join(config);
+            if !path.is_file() {
+                let msg = format!("Configuration file {} does not exist.", path.display());
+                error!(msg);
+                return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+            }
+            path
+        }
+        None => {
+            let path = dir.join(CONFIG_FILE_NAME);
+            if !path.is_file() {
+                return Ok(());
+            }
+            path
+        }
+    };
+    info!(?path, "⚙️ Using the settings of the configuration file.");
+    let config = ConfigFile::read(&path)?;
+    let base = path.parent().unwrap_or(dir);
+    let from_file = |id: &str| !is_explicit(matches, id);
+
+    macro_rules! set {
+        ($field:ident) => {
+            if let Some(value) = config.$field.clone()
+                && from_file(stringify!($field))
+            {
+                opts.$field = value;
+            }
+        };
+        ($field:ident, Some) => {
+            if let Some(value) = config.$field.clone()
+                && from_file(stringify!($field))
+            {
+                opts.$field = Some(value);
+            }
+        };
+        ($field:ident, $config_field:ident) => {
+            if !config.$config_field.is_empty() && from_file(stringify!($field)) {
+                opts.$field = config.$config_field.clone();
+            }
+        };
+    }
+
+    set!(method);
+    set!(registry_layout);
+    set!(tag, Some);
+    set!(custom_root, Some);
+    set!(no_root_manifest, Some);
+    set!(update);
+    set!(respect_lockfile);
+    set!(skip_unchanged);
+    set!(offline);
+    set!(deny_floating_git_refs);
+    set!(multiple_versions, Some);
+    set!(trust_upstream_audit_config);
+    set!(cargo_vet);
+    set!(vet_criteria, Some);
+    set!(allowed_git_host, allowed_git_hosts);
+    set!(allowed_registry, allowed_registries);
+    set!(allowed_git, allowed_git);
+    set!(deny_crate, deny_crates);
+    if let Some(compression) = &config.compression
+        && from_file("compression")
+        && let Ok(compression) = Compression::from_str(compression, true)
+    {
+        opts.compression = compression;
+    }
+    for (id, field, value) in [
+        ("crate_cache", &mut opts.crate_cache, &config.crate_cache),
+        (
+            "local_registry",
+            &mut opts.local_registry,
+            &config.local_registry,
+        ),
+        ("policy", &mut opts.policy, &config.policy),
+    ] {
+        if let Some(value) = value
+            && from_file(id)
+        {
+            *field = Some(base.join(value));
+        }
+    }
+    if !config.vet_audits.is_empty() && from_file("vet_audits") {
+        opts.vet_audits = config
+            .vet_audits
+            .iter()
+            .map(|audits| base.join(audits))
+            .collect();
+    }
+    if !config.alternate_registries.is_empty() && from_file("alternate_registry") {
+        opts.alternate_registry = config
+            .alternate_registries
+            .iter()
+            .map(|(name, index)| format!("{name}={index}"))
+            .collect();
+    }
+    if !config.accepted_risks.is_empty() && from_file("i_accept_the_risk") {
+        for risk in &config.accepted_risks {
+            info!("⚠️  Accepting {} because {}", risk.id, risk.reason);
+        }
+        opts.i_accept_the_risk = config
+            .accepted_risks
+            .iter()
+            .map(|risk| risk.id.clone())
+            .collect();
+    }
+    if !config.manifests.is_empty() && from_file("manifest_path") {
+        opts.manifest_path = config
+            .manifests
+            .iter()
+            .map(|manifest| manifest.path.clone())
+            .collect();
+    }
+    let mut update_crate = config.update_crates.clone();
+    for manifest in &config.manifests {
+        update_crate.extend(
+            manifest
+                .update_crates
+                .iter()
+                .map(|spec| format!("{}+{}", spec, manifest.path.display())),
+        );
+    }
+    if !update_crate.is_empty() && from_file("update_crate") {
+        opts.update_crate = update_crate;
+    }
+    let vendor_args = opts
+        .vendor_specific_args
+        .get_or_insert_with(VendorArgs::default);
+    if let Some(versioned_dirs) = config.versioned_dirs
+        && from_file("versioned_dirs")
+    {
+        vendor_args.versioned_dirs = versioned_dirs;
+    }
+    if let Some(filter) = config.filter
+        && from_file("filter")
+    {
+        vendor_args.filter = filter;
+    }
+    debug!(?opts);
+    Ok(())
+}
+
+/// Parse the arguments and fill in the configuration file of the package in
+/// `dir`.
+pub fn parse_with_config_file<I, T>(args: I, dir: &Path) -> io::Result<Opts>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let to_io_error = |err: clap::Error| {
+        error!(%err, "🛑 Invalid parameters.");
+        let msg = err
+            .to_string()
+            .lines()
+            .map(str::trim)
+            .take_while(|line| !line.starts_with("Usage:"))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        io::Error::new(io::ErrorKind::InvalidInput, msg)
+    };
+    let matches = Opts::command()
+        .try_get_matches_from(args)
+        .map_err(to_io_error)?;
+    let mut opts = Opts::from_arg_matches(&matches).map_err(to_io_error)?;
+    apply_config_file(&mut opts, &matches, dir)?;
+    Ok(opts)
+}
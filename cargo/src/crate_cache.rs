@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use rustsec::cargo_lock::Package;
+use sha2::{Digest, Sha256};
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+
+/// Where the `.crate` files are kept, named after their sha256 checksum.
+const CRATES_DIR: &str = "crates";
+/// Names of the directories cargo used for crates.io in `registry/cache`.
+/// They depend on the version of cargo, so every name seen is remembered.
+const REGISTRY_DIRS_DIR: &str = "registry-dirs";
+
+// NOTE: Only crates.io is cached. Its directories are named after the index
+// host, e.g. `index.crates.io-1949cf8c6b5b557f`.
+const CRATES_IO_DIR_PREFIXES: &[&str] = &["index.crates.io-", "github.com-"];
+
+/// A directory of verified `.crate` files shared between runs. Files are only
+/// ever added with a rename, so concurrent runs never see partial files.
+#[derive(Debug, Clone)]
+pub struct CrateCache {
+    root: PathBuf,
+}
+
+pub(crate) fn sha256_of(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// crates.io packages of the lockfile with their checksum.
+fn cacheable_packages(lockfile: &Path) -> io::Result<Vec<(Package, String)>> {
+    Ok(load_lockfile(lockfile)?
+        .packages
+        .into_iter()
+        .filter(|package| {
+            package
+                .source
+                .as_ref()
+                .is_some_and(|source| source.is_default_registry())
+        })
+        .filter_map(|package| {
+            let checksum = package.checksum.as_ref()?.to_string();
+            Some((package, checksum))
+        })
+        .collect())
+}
+
+fn crate_file_name(package: &Package) -> String {
+    format!("{}-{}.crate", package.name, package.version)
+}
+
+fn dir_names(dir: &Path) -> io::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            names.insert(entry?.file_name().to_string_lossy().to_string());
+        }
+    }
+    Ok(names)
+}
+
+fn is_crates_io_dir(name: &str) -> bool {
+    CRATES_IO_DIR_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+impl CrateCache {
+    pub fn open(root: &Path) -> io::Result<Self> {
+        fs::create_dir_all(root.join(CRATES_DIR))?;
+        fs::create_dir_all(root.join(REGISTRY_DIRS_DIR))?;
+        Ok(CrateCache {
+            root: root.to_path_buf(),
+        })
+    }
+
+    /// Where the crate with the checksum is kept in the cache at `root`.
+    pub fn crate_path_in(root: &Path, checksum: &str) -> PathBuf {
+        root.join(CRATES_DIR).join(format!("{checksum}.crate"))
+    }
+
+    fn crate_path(&self, checksum: &str) -> PathBuf {
+        CrateCache::crate_path_in(&self.root, checksum)
+    }
+
+    /// Copy the cached crates of the lockfile into `registry/cache` of the
+    /// cargo home so that cargo does not download them again. Cargo does not
+    /// check crates it finds there, so they are verified first.
+    pub fn seed(&self, cargo_home: &Path, lockfile: &Path) -> io::Result<usize> {
+        let mut registry_dirs = dir_names(&self.root.join(REGISTRY_DIRS_DIR))?;
+        registry_dirs.extend(
+            dir_names(&cargo_home.join("registry").join("index"))?
+                .into_iter()
+                .filter(|name| is_crates_io_dir(name)),
+        );
+        let mut seeded = 0;
+        for (package, checksum) in cacheable_packages(lockfile)? {
+            let cached = self.crate_path(&checksum);
+            if !cached.is_file() {
+                continue;
+            }
+            if sha256_of(&cached)? != checksum {
+                warn!(
+                    ?cached,
+                    "⚠️ Removing cached crate {} {} with a wrong checksum.",
+                    package.name,
+                    package.version
+                );
+                fs::remove_file(&cached)?;
+                continue;
+            }
+            for registry_dir in &registry_dirs {
+                let target_dir = cargo_home.join("registry").join("cache").join(registry_dir);
+                fs::create_dir_all(&target_dir)?;
+                let target = target_dir.join(crate_file_name(&package));
+                if !target.exists() {
+                    fs::copy(&cached, &target)?;
+                }
+            }
+            seeded += 1;
+        }
+        info!("🗄️ Seeded {} crates from the crate cache.", seeded);
+        Ok(seeded)
+    }
+
+    /// Add the crates of the lockfile that cargo downloaded to the cache and
+    /// remember which directories cargo used. Directories that were seeded but
+    /// not used by cargo are removed so they don't end up in the tarball.
+    pub fn harvest(&self, cargo_home: &Path, lockfile: &Path) -> io::Result<usize> {
+        let registry_cache = cargo_home.join("registry").join("cache");
+        let used_dirs = dir_names(&cargo_home.join("registry").join("index"))?;
+        for registry_dir in dir_names(&registry_cache)? {
+            if !used_dirs.contains(&registry_dir) {
+                debug!(?registry_dir, "Removing unused registry cache directory.");
+                fs::remove_dir_all(registry_cache.join(&registry_dir))?;
+            }
+        }
+        let mut harvested = 0;
+        for (package, checksum) in cacheable_packages(lockfile)? {
+            for registry_dir in used_dirs.iter().filter(|name| is_crates_io_dir(name)) {
+                let downloaded = registry_cache
+                    .join(registry_dir)
+                    .join(crate_file_name(&package));
+                if !downloaded.is_file() {
+                    continue;
+                }
+                fs::File::create(self.root.join(REGISTRY_DIRS_DIR).join(registry_dir))?;
+                let cached = self.crate_path(&checksum);
+                if cached.is_file() {
+                    continue;
+                }
+                if sha256_of(&downloaded)? != checksum {
+                    warn!(
+                        ?downloaded,
+                        "⚠️ Not caching crate {} {} with a wrong checksum.",
+                        package.name,
+                        package.version
+                    );
+                    continue;
+                }
+                let partial = tempfile::NamedTempFile::new_in(self.root.join(CRATES_DIR))?;
+                fs::copy(&downloaded, partial.path())?;
+                partial.persist(&cached).map_err(|err| err.error)?;
+                harvested += 1;
+            }
+        }
+        info!("🗄️ Added {} crates to the crate cache.", harvested);
+        Ok(harvested)
+    }
+}
@@ -0,0 +1,395 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::common::Compression;
+use libroast::operations::cli::RawArgs;
+use libroast::operations::raw::raw_opts;
+use rustsec::cargo_lock::Package;
+use semver::Version;
+use serde::Serialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+use crate::audit::perform_cargo_audit;
+use crate::cli::Opts;
+
+const COMPRESSIONS: &[Compression] = &[
+    Compression::Zst,
+    Compression::Gz,
+    Compression::Xz,
+    Compression::Bz2,
+    Compression::Not,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrateChange {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChange {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceChange {
+    pub name: String,
+    pub version: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct AdvisoryChange {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub title: String,
+}
+
+/// Difference between the lockfiles of the previous archive and the new ones.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyChanges {
+    pub previous_archive: PathBuf,
+    pub added: Vec<CrateChange>,
+    pub removed: Vec<CrateChange>,
+    pub upgraded: Vec<VersionChange>,
+    pub downgraded: Vec<VersionChange>,
+    pub source_changed: Vec<SourceChange>,
+    /// Advisories affecting the new lockfiles but not the previous ones.
+    /// Empty if the advisory database is not available.
+    pub new_advisories: Vec<AdvisoryChange>,
+    /// Advisories affecting the previous lockfiles but not the new ones.
+    pub fixed_advisories: Vec<AdvisoryChange>,
+}
+
+impl DependencyChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.upgraded.is_empty()
+            && self.downgraded.is_empty()
+            && self.source_changed.is_empty()
+            && self.new_advisories.is_empty()
+            && self.fixed_advisories.is_empty()
+    }
+}
+
+/// Source of a package including the commit of git dependencies, since a git
+/// dependency can move without changing its version.
+pub(crate) fn source_of(package: &Package) -> Option<String> {
+    package
+        .source
+        .as_ref()
+        .map(|source| match source.precise() {
+            Some(precise) if source.is_git() => format!("{source}#{precise}"),
+            _ => source.to_string(),
+        })
+}
+
+/// Versions and sources of every non-local package in the lockfiles.
+pub type ResolvedCrates = BTreeMap<String, BTreeMap<Version, Option<String>>>;
+
+pub fn resolved_crates(lockfiles: &[impl AsRef<Path>]) -> io::Result<ResolvedCrates> {
+    let mut crates = ResolvedCrates::new();
+    for lockfile in lockfiles {
+        let lockfile = load_lockfile(lockfile.as_ref())?;
+        for package in lockfile.packages {
+            if package.source.is_none() {
+                continue;
+            }
+            let source = source_of(&package);
+            crates
+                .entry(package.name.to_string())
+                .or_default()
+                .insert(package.version, source);
+        }
+    }
+    Ok(crates)
+}
+
+/// Compare two sets of resolved crates. If a crate lost and gained versions,
+/// they are paired in order as upgrades or downgrades.
+pub fn compare(previous: &ResolvedCrates, current: &ResolvedCrates) -> DependencyChanges {
+    let mut changes = DependencyChanges::default();
+    let empty = BTreeMap::new();
+    let names: BTreeSet<&String> = previous.keys().chain(current.keys()).collect();
+    for name in names {
+        let old = previous.get(name).unwrap_or(&empty);
+        let new = current.get(name).unwrap_or(&empty);
+        for (version, source) in old {
+            if let Some(new_source) = new.get(version)
+                && new_source != source
+            {
+                changes.source_changed.push(SourceChange {
+                    name: name.clone(),
+                    version: version.to_string(),
+                    from: source.clone(),
+                    to: new_source.clone(),
+                });
+            }
+        }
+        let removed: Vec<&Version> = old.keys().filter(|v| !new.contains_key(*v)).collect();
+        let added: Vec<&Version> = new.keys().filter(|v| !old.contains_key(*v)).collect();
+        for (from, to) in removed.iter().zip(added.iter()) {
+            let change = VersionChange {
+                name: name.clone(),
+                from: from.to_string(),
+                to: to.to_string(),
+                source: new.get(*to).cloned().flatten(),
+            };
+            if to > from {
+                changes.upgraded.push(change);
+            } else {
+                changes.downgraded.push(change);
+            }
+        }
+        let paired = removed.len().min(added.len());
+        for version in removed.iter().skip(paired) {
+            changes.removed.push(CrateChange {
+                name: name.clone(),
+                version: version.to_string(),
+                source: old.get(*version).cloned().flatten(),
+            });
+        }
+        for version in added.iter().skip(paired) {
+            changes.added.push(CrateChange {
+                name: name.clone(),
+                version: version.to_string(),
+                source: new.get(*version).cloned().flatten(),
+            });
+        }
+    }
+    changes
+}
+
+/// The archive of a previous run, looked up in `outdir` and then in the
+/// current directory, which is where OBS keeps the committed files while
+/// services write to a temporary `outdir`.
+pub fn find_previous_archive(opts: &Opts) -> io::Result<Option<PathBuf>> {
+    if let Some(previous_archive) = &opts.previous_archive {
+        return Ok(Some(previous_archive.clone()));
+    }
+    let mut names = vec![opts.archive_name(&opts.compression)?];
+    for compression in COMPRESSIONS {
+        names.push(opts.archive_name(compression)?);
+    }
+    for dir in [opts.outdir.clone(), PathBuf::from(".")] {
+        if let Some(found) = names
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+        {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+/// Lockfiles of an extracted archive. The vendored crates and the registry
+/// are skipped since crates may ship their own lockfile.
+pub(crate) fn find_lockfiles(dir: &Path, lockfiles: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let skip = path
+                .file_name()
+                .is_some_and(|name| name == "vendor" || name == ".cargo");
+            if !skip {
+                find_lockfiles(&path, lockfiles)?;
+            }
+        } else if path.file_name().is_some_and(|name| name == "Cargo.lock") {
+            lockfiles.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Extract a tarball into a temporary directory, which is removed with the
+/// returned handle.
+pub(crate) fn extract_to_tempdir(archive: &Path) -> io::Result<tempfile::TempDir> {
+    let tempdir = tempfile::Builder::new()
+        .prefix(".extracted-archive")
+        .rand_bytes(12)
+        .tempdir()?;
+    raw_opts(
+        RawArgs {
+            target: Some(archive.to_path_buf()),
+            outdir: Some(tempdir.path().to_path_buf()),
+            silent: true,
+            subcommands: None,
+        },
+        false,
+    )
+    .inspect_err(|err| {
+        error!(?err, ?archive, "Failed to extract the archive.");
+    })?;
+    Ok(tempdir)
+}
+
+/// Extract an archive to a temporary directory and find its lockfiles. The
+/// lockfiles are removed with the returned directory.
+pub(crate) fn archive_lockfiles(archive: &Path) -> io::Result<(tempfile::TempDir, Vec<PathBuf>)> {
+    let tempdir = extract_to_tempdir(archive)?;
+    let mut lockfiles = Vec::new();
+    find_lockfiles(tempdir.path(), &mut lockfiles)?;
+    lockfiles.sort();
+    Ok((tempdir, lockfiles))
+}
+
+/// Vulnerabilities found in the lockfiles, or nothing if the advisory
+/// database is not available.
+fn advisories(
+    lockfiles: &[impl AsRef<Path>],
+    i_accept_the_risk: &[String],
+) -> Option<BTreeSet<AdvisoryChange>> {
+    let reports = perform_cargo_audit(lockfiles, i_accept_the_risk)
+        .inspect_err(|err| debug!(?err, "Advisory database is not available."))
+        .ok()?;
+    Some(
+        reports
+            .into_iter()
+            .flat_map(|report| report.vulnerabilities.list)
+            .map(|vulnerability| AdvisoryChange {
+                id: vulnerability.advisory.id.to_string(),
+                name: vulnerability.package.name.to_string(),
+                version: vulnerability.package.version.to_string(),
+                title: vulnerability.advisory.title,
+            })
+            .collect(),
+    )
+}
+
+/// Compare the lockfiles, including the advisories that affect them, and fill
+/// in the new and fixed advisories.
+pub fn compare_lockfiles(
+    previous_lockfiles: &[impl AsRef<Path>],
+    lockfiles: &[impl AsRef<Path>],
+    i_accept_the_risk: &[String],
+) -> io::Result<DependencyChanges> {
+    let mut changes = compare(
+        &resolved_crates(previous_lockfiles)?,
+        &resolved_crates(lockfiles)?,
+    );
+    if let (Some(previous), Some(current)) = (
+        advisories(previous_lockfiles, i_accept_the_risk),
+        advisories(lockfiles, i_accept_the_risk),
+    ) {
+        changes.new_advisories = current.difference(&previous).cloned().collect();
+        changes.fixed_advisories = previous.difference(&current).cloned().collect();
+    }
+    Ok(changes)
+}
+
+fn log_changes(changes: &DependencyChanges) {
+    info!(
+        "📊 Dependency changes since {}:",
+        changes.previous_archive.display()
+    );
+    if changes.is_empty() {
+        info!("📊 No dependency changes.");
+        return;
+    }
+    for change in &changes.added {
+        info!("➕ {} {}", change.name, change.version);
+    }
+    for change in &changes.removed {
+        info!("➖ {} {}", change.name, change.version);
+    }
+    for change in &changes.upgraded {
+        info!("⬆️ {} {} -> {}", change.name, change.from, change.to);
+    }
+    for change in &changes.downgraded {
+        warn!("⬇️ {} {} -> {}", change.name, change.from, change.to);
+    }
+    for change in &changes.source_changed {
+        warn!(
+            "🔀 {} {} moved from {} to {}",
+            change.name,
+            change.version,
+            change.from.as_deref().unwrap_or("a local path"),
+            change.to.as_deref().unwrap_or("a local path")
+        );
+    }
+    for advisory in &changes.new_advisories {
+        warn!(
+            "🚨 {} {} is affected by {}: {}",
+            advisory.name, advisory.version, advisory.id, advisory.title
+        );
+    }
+    for advisory in &changes.fixed_advisories {
+        info!(
+            "✅ {} {} is no longer affected by {}: {}",
+            advisory.name, advisory.version, advisory.id, advisory.title
+        );
+    }
+    info!(
+        "📊 {} added, {} removed, {} upgraded, {} downgraded, {} changed sources, {} new advisories, {} fixed advisories.",
+        changes.added.len(),
+        changes.removed.len(),
+        changes.upgraded.len(),
+        changes.downgraded.len(),
+        changes.source_changed.len(),
+        changes.new_advisories.len(),
+        changes.fixed_advisories.len()
+    );
+}
+
+/// Compare the lockfiles with the ones of the previous archive, if there is
+/// one. Must run before the new archive overwrites it.
+pub fn report_dependency_changes(
+    lockfiles: &[impl AsRef<Path>],
+    opts: &Opts,
+) -> io::Result<Option<DependencyChanges>> {
+    // NOTE: A verification run must not pick up a tarball of the current
+    // directory. Its lockfiles are the ones of the verified tarball anyway.
+    if opts.verify_lockfiles_from.is_some() {
+        debug!("Skipping the dependency change report while verifying.");
+        return Ok(None);
+    }
+    let Some(previous_archive) = find_previous_archive(opts)? else {
+        info!("📊 No previous archive found. Skipping the dependency change report.");
+        return Ok(None);
+    };
+    info!(
+        ?previous_archive,
+        "📊 Comparing dependencies with the previous archive."
+    );
+    let (_extracted, previous_lockfiles) = archive_lockfiles(&previous_archive)?;
+    debug!(?previous_lockfiles);
+
+    let mut changes = compare_lockfiles(&previous_lockfiles, lockfiles, &opts.i_accept_the_risk)?;
+    changes.previous_archive = previous_archive;
+    log_changes(&changes);
+
+    if let Some(path) = &opts.dependency_report {
+        let content = serde_json::to_string_pretty(&changes).map_err(|err| {
+            error!(?err, "Failed to serialize dependency report.");
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?;
+        fs::write(path, content).inspect_err(|err| {
+            error!(?err, ?path, "Failed to write dependency report.");
+        })?;
+        info!(?path, "📊 Wrote dependency report.");
+    }
+    Ok(Some(changes))
+}
@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use rustsec::cargo_lock::package::GitReference;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+use crate::toml_manifest::dependency_references;
+
+/// Where a git dependency was declared in a manifest.
+#[derive(Debug, Clone)]
+pub struct GitDeclaration {
+    pub manifest: PathBuf,
+    pub line: usize,
+}
+
+/// A crate that was resolved from a git repository instead of a registry.
+#[derive(Debug, Clone)]
+pub struct GitDependency {
+    pub name: String,
+    pub version: String,
+    pub url: url::Url,
+    pub reference: GitReference,
+    pub commit: Option<String>,
+    pub lockfile: PathBuf,
+    pub declared_in: Vec<GitDeclaration>,
+}
+
+impl GitDependency {
+    /// A branch, or the default branch, can point to another commit the
+    /// next time the lockfile is regenerated or updated.
+    pub fn is_floating(&self) -> bool {
+        matches!(
+            self.reference,
+            GitReference::DefaultBranch | GitReference::Branch(_)
+        )
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.url.host_str()
+    }
+
+    pub fn requested_ref(&self) -> String {
+        match &self.reference {
+            GitReference::DefaultBranch => "default branch".to_string(),
+            GitReference::Branch(branch) => format!("branch={branch}"),
+            GitReference::Tag(tag) => format!("tag={tag}"),
+            GitReference::Rev(rev) => format!("rev={rev}"),
+        }
+    }
+}
+
+impl fmt::Display for GitDependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} from {} ({}) resolved to {}",
+            self.name,
+            self.version,
+            self.url,
+            self.requested_ref(),
+            self.commit.as_deref().unwrap_or("an unknown commit")
+        )
+    }
+}
+
+/// Collect every git dependency of the lockfiles. The manifests are only used
+/// to point out where a dependency was declared.
+pub fn collect_git_dependencies(
+    setup_workdir: &Path,
+    lockfiles: &[impl AsRef<Path>],
+    manifests: &[impl AsRef<Path>],
+) -> io::Result<Vec<GitDependency>> {
+    let mut declarations: Vec<(String, String, GitDeclaration)> = Vec::new();
+    for manifest in manifests {
+        let manifest = manifest.as_ref();
+        for reference in dependency_references(manifest)? {
+            if let Some(git) = reference.field("git") {
+                // NOTE: `package` renames the dependency in the manifest.
+                let name = reference
+                    .field("package")
+                    .unwrap_or(&reference.name)
+                    .to_string();
+                declarations.push((
+                    name,
+                    git.trim_end_matches('/')
+                        .trim_end_matches(".git")
+                        .to_string(),
+                    GitDeclaration {
+                        manifest: reference
+                            .manifest
+                            .strip_prefix(setup_workdir)
+                            .unwrap_or(&reference.manifest)
+                            .to_path_buf(),
+                        line: reference.line_of("git").unwrap_or(1),
+                    },
+                ));
+            }
+        }
+    }
+
+    let mut git_dependencies = Vec::new();
+    for lockfile_path in lockfiles {
+        let lockfile_path = lockfile_path.as_ref();
+        let lockfile = load_lockfile(lockfile_path)?;
+        for package in lockfile.packages {
+            let Some(source) = &package.source else {
+                continue;
+            };
+            let Some(reference) = source.git_reference() else {
+                continue;
+            };
+            let url = source.url().clone();
+            let normalized_url = url
+                .as_str()
+                .trim_end_matches('/')
+                .trim_end_matches(".git")
+                .to_string();
+            let declared_in = declarations
+                .iter()
+                .filter(|(name, git, _)| *name == package.name.as_str() && *git == normalized_url)
+                .map(|(_, _, declaration)| declaration.clone())
+                .collect();
+            git_dependencies.push(GitDependency {
+                name: package.name.to_string(),
+                version: package.version.to_string(),
+                url,
+                reference: reference.clone(),
+                commit: source.precise().map(str::to_string),
+                lockfile: lockfile_path.to_path_buf(),
+                declared_in,
+            });
+        }
+    }
+    git_dependencies.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    git_dependencies.dedup_by(|a, b| a.name == b.name && a.version == b.version && a.url == b.url);
+    Ok(git_dependencies)
+}
+
+/// Report every git dependency and fail if one of them follows a branch
+/// while floating refs are denied. Where git dependencies are allowed to come
+/// from is part of the source policy.
+pub fn check_git_dependencies(
+    git_dependencies: &[GitDependency],
+    deny_floating_refs: bool,
+) -> io::Result<()> {
+    if git_dependencies.is_empty() {
+        info!("🐙 No git dependencies found.");
+        return Ok(());
+    }
+
+    info!("🐙 Found {} git dependencies:", git_dependencies.len());
+    let mut violations = 0;
+    for git_dependency in git_dependencies {
+        info!("- {}", git_dependency);
+        for declaration in &git_dependency.declared_in {
+            info!(
+                "  declared at {}:{}",
+                declaration.manifest.display(),
+                declaration.line
+            );
+        }
+        if git_dependency.is_floating() {
+            if deny_floating_refs {
+                violations += 1;
+                error!(
+                    "🛑 {} follows a floating ref ({}). Pin it with `rev` upstream.",
+                    git_dependency.name,
+                    git_dependency.requested_ref()
+                );
+            } else {
+                warn!(
+                    "⚠️ {} follows a floating ref ({}). Updating the lockfile may pull in other commits.",
+                    git_dependency.name,
+                    git_dependency.requested_ref()
+                );
+            }
+        }
+    }
+    warn!(
+        "⚠️ RustSec can only audit crates from crates.io. These {} git dependencies escaped the advisory check.",
+        git_dependencies.len()
+    );
+
+    if violations > 0 {
+        let msg = format!("Found {violations} git dependencies that follow a floating ref.");
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    Ok(())
+}
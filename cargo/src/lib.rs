@@ -6,10 +6,33 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+pub mod alternate_registries;
+pub mod archive_manifest;
 pub mod audit;
+pub mod bans;
+pub mod bulk_update;
 pub mod cargo_commands;
+pub mod changelog;
 pub mod cli;
+pub mod config_file;
 pub mod consts;
+pub mod crate_cache;
+pub mod dependency_changes;
+pub mod git_deps;
+pub mod local_registry;
+pub mod merge;
+pub mod offline;
+pub mod plan;
+pub mod policy;
+pub mod preflight;
+pub mod prune;
 pub mod registry;
+pub mod service_definition;
+pub mod service_file;
+pub mod staleness;
 pub(crate) mod toml_manifest;
+pub mod upstream_config;
+pub mod upstream_hints;
 pub mod vendor;
+pub mod verify;
+pub mod vet;
@@ -0,0 +1,287 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+use crate::cargo_commands::cargo_metadata_offline;
+
+/// Where the local registry is kept in the tarball, relative to `.cargo`.
+pub const LOCAL_REGISTRY_DIR: &str = "local-registry";
+/// Name of the source replacing crates.io in the `.cargo/config.toml` of the
+/// tarball.
+pub const LOCAL_REGISTRY_SOURCE_NAME: &str = "vendored-registry";
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<MetadataPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    dependencies: Vec<MetadataDependency>,
+    features: BTreeMap<String, Vec<String>>,
+    links: Option<String>,
+    rust_version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataDependency {
+    name: String,
+    req: String,
+    kind: Option<String>,
+    rename: Option<String>,
+    optional: bool,
+    uses_default_features: bool,
+    features: Vec<String>,
+    target: Option<String>,
+    registry: Option<String>,
+}
+
+/// A dependency in the registry index format.
+#[derive(Debug, Serialize)]
+struct IndexDependency {
+    name: String,
+    req: String,
+    features: Vec<String>,
+    optional: bool,
+    default_features: bool,
+    target: Option<String>,
+    kind: String,
+    registry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package: Option<String>,
+}
+
+/// A line of a registry index file.
+#[derive(Debug, Serialize)]
+struct IndexEntry {
+    name: String,
+    vers: String,
+    deps: Vec<IndexDependency>,
+    cksum: String,
+    features: BTreeMap<String, Vec<String>>,
+    yanked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    links: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rust_version: Option<String>,
+}
+
+impl IndexEntry {
+    fn new(package: MetadataPackage, cksum: String) -> Self {
+        let deps = package
+            .dependencies
+            .into_iter()
+            .map(|dependency| {
+                let (name, package) = match dependency.rename {
+                    Some(rename) => (rename, Some(dependency.name)),
+                    None => (dependency.name, None),
+                };
+                IndexDependency {
+                    name,
+                    req: dependency.req,
+                    features: dependency.features,
+                    optional: dependency.optional,
+                    default_features: dependency.uses_default_features,
+                    target: dependency.target,
+                    kind: dependency.kind.unwrap_or_else(|| "normal".to_string()),
+                    registry: dependency.registry,
+                    package,
+                }
+            })
+            .collect();
+        IndexEntry {
+            name: package.name,
+            vers: package.version,
+            deps,
+            cksum,
+            features: package.features,
+            yanked: false,
+            links: package.links,
+            rust_version: package.rust_version,
+        }
+    }
+}
+
+/// Path of the index file of a crate, following the layout of crates.io.
+fn index_path(name: &str) -> PathBuf {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => PathBuf::from("1").join(&name),
+        2 => PathBuf::from("2").join(&name),
+        3 => PathBuf::from("3").join(&name[..1]).join(&name),
+        _ => PathBuf::from(&name[..2]).join(&name[2..4]).join(&name),
+    }
+}
+
+fn cargo_metadata(manifest: &Path) -> io::Result<Metadata> {
+    let output = cargo_metadata_offline(manifest).inspect_err(|err| {
+        error!(?err, ?manifest, "Failed to run `cargo metadata`.");
+    })?;
+    serde_json::from_str(&output).map_err(|err| {
+        error!(?err, ?manifest);
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    })
+}
+
+/// The `.crate` file cargo downloaded into the cargo home.
+fn downloaded_crate(cargo_home: &Path, name: &str, version: &str) -> io::Result<Option<PathBuf>> {
+    let registry_cache = cargo_home.join("registry").join("cache");
+    if !registry_cache.is_dir() {
+        return Ok(None);
+    }
+    for entry in fs::read_dir(&registry_cache)? {
+        let crate_file = entry?.path().join(format!("{name}-{version}.crate"));
+        if crate_file.is_file() {
+            return Ok(Some(crate_file));
+        }
+    }
+    Ok(None)
+}
+
+/// The `.cargo/config.toml` replacing crates.io with the local registry.
+fn local_registry_config() -> io::Result<String> {
+    let mut local_registry = toml::Table::new();
+    local_registry.insert(
+        "local-registry".to_string(),
+        toml::Value::String(format!(".cargo/{LOCAL_REGISTRY_DIR}")),
+    );
+    let mut crates_io = toml::Table::new();
+    crates_io.insert(
+        "replace-with".to_string(),
+        toml::Value::String(LOCAL_REGISTRY_SOURCE_NAME.to_string()),
+    );
+    let mut source = toml::Table::new();
+    source.insert("crates-io".to_string(), toml::Value::Table(crates_io));
+    source.insert(
+        LOCAL_REGISTRY_SOURCE_NAME.to_string(),
+        toml::Value::Table(local_registry),
+    );
+    let mut config = toml::Table::new();
+    config.insert("source".to_string(), toml::Value::Table(source));
+    toml::to_string(&config).map_err(|err| {
+        error!(?err);
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    })
+}
+
+/// Turn the crates that cargo fetched into the cargo home into a cargo local
+/// registry holding only the crates of the lockfiles. Everything else in the
+/// cargo home is removed and a `config.toml` replacing crates.io with the
+/// local registry is written, so the `.cargo` directory of the tarball works
+/// as project configuration. Returns the number of crates in the registry.
+pub fn create_local_registry(cargo_home: &Path, lockfiles: &[PathBuf]) -> io::Result<usize> {
+    info!("🗃️ Creating a local registry from the fetched crates.");
+    let mut checksums = BTreeMap::new();
+    let mut problems = Vec::new();
+    for lockfile in lockfiles.iter().filter(|lockfile| lockfile.is_file()) {
+        for package in load_lockfile(lockfile)?.packages {
+            match (&package.source, &package.checksum) {
+                (None, _) => {}
+                (Some(source), Some(checksum)) if source.is_default_registry() => {
+                    checksums.insert(
+                        (package.name.to_string(), package.version.to_string()),
+                        checksum.to_string(),
+                    );
+                }
+                (Some(source), _) => problems.push(format!(
+                    "{} {} from {} can't be put into a local registry",
+                    package.name, package.version, source
+                )),
+            }
+        }
+    }
+
+    let local_registry = cargo_home.join(LOCAL_REGISTRY_DIR);
+    let mut entries: BTreeMap<(String, String), IndexEntry> = BTreeMap::new();
+    for lockfile in lockfiles.iter().filter(|lockfile| lockfile.is_file()) {
+        for package in cargo_metadata(&lockfile.with_file_name("Cargo.toml"))?.packages {
+            if package.source.is_none() {
+                continue;
+            }
+            let key = (package.name.clone(), package.version.clone());
+            if entries.contains_key(&key) {
+                continue;
+            }
+            if let Some(checksum) = checksums.get(&key) {
+                entries.insert(key, IndexEntry::new(package, checksum.clone()));
+            }
+        }
+    }
+    for (name, version) in checksums.keys() {
+        if !entries.contains_key(&(name.clone(), version.clone())) {
+            problems.push(format!("{name} {version} is not known to `cargo metadata`"));
+        }
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("🛑 {}", problem);
+        }
+        let msg = format!(
+            "Found {} crates that can't be put into a local registry. Use the `cargo-home` registry layout instead.",
+            problems.len()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+    }
+
+    fs::create_dir_all(local_registry.join("index"))?;
+    let mut index_files: BTreeMap<PathBuf, String> = BTreeMap::new();
+    for ((name, version), entry) in &entries {
+        let Some(crate_file) = downloaded_crate(cargo_home, name, version)? else {
+            let msg = format!("Crate {name} {version} was not downloaded by cargo.");
+            error!(msg);
+            return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+        };
+        fs::copy(
+            crate_file,
+            local_registry.join(format!("{name}-{version}.crate")),
+        )?;
+        let line = serde_json::to_string(entry).map_err(|err| {
+            error!(?err);
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?;
+        let index_file = index_files.entry(index_path(name)).or_default();
+        index_file.push_str(&line);
+        index_file.push('\n');
+    }
+    for (index_file, content) in index_files {
+        let index_file = local_registry.join("index").join(index_file);
+        if let Some(parent) = index_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(index_file, content)?;
+    }
+
+    for entry in fs::read_dir(cargo_home)? {
+        let entry = entry?;
+        if entry.file_name() == LOCAL_REGISTRY_DIR {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    fs::write(cargo_home.join("config.toml"), local_registry_config()?)?;
+    info!("🗃️ Added {} crates to the local registry.", entries.len());
+    Ok(entries.len())
+}
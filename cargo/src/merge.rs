@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::utils;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::archive_manifest::ArchiveManifest;
+use crate::archive_manifest::MANIFEST_FILE_NAME;
+use crate::audit::load_lockfile;
+use crate::cli::Method;
+use crate::cli::Opts;
+use crate::dependency_changes::extract_to_tempdir;
+use crate::dependency_changes::find_lockfiles;
+use crate::local_registry::LOCAL_REGISTRY_DIR;
+use crate::prune::prune_registry;
+
+/// Name of the source `cargo vendor` replaces the others with.
+const VENDORED_SOURCES: &str = "vendored-sources";
+
+/// The archive to merge with, looked up in `outdir` and then as given.
+fn find_archive(opts: &Opts, merge_into: &Path) -> io::Result<PathBuf> {
+    [opts.outdir.join(merge_into), merge_into.to_path_buf()]
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            let msg = format!(
+                "Archive {} to merge with does not exist.",
+                merge_into.display()
+            );
+            error!(msg);
+            io::Error::new(io::ErrorKind::NotFound, msg)
+        })
+}
+
+/// The method of an extracted archive, from its manifest or, for archives of
+/// older versions, from its layout.
+fn method_of(extracted: &Path) -> io::Result<String> {
+    if let Some(manifest) = ArchiveManifest::read(extracted)? {
+        return Ok(manifest.method);
+    }
+    let dot_cargo = extracted.join(".cargo");
+    let is_registry = ["registry", "git", LOCAL_REGISTRY_DIR]
+        .iter()
+        .any(|dir| dot_cargo.join(dir).is_dir());
+    Ok(if is_registry { "registry" } else { "vendor" }.to_string())
+}
+
+fn lockfiles_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut lockfiles = Vec::new();
+    find_lockfiles(dir, &mut lockfiles)?;
+    lockfiles.sort();
+    Ok(lockfiles)
+}
+
+/// Directories of the lockfiles, relative to the root of the archive.
+fn placements(root: &Path) -> io::Result<BTreeSet<PathBuf>> {
+    Ok(lockfiles_in(root)?
+        .iter()
+        .filter_map(|lockfile| lockfile.parent()?.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .collect())
+}
+
+/// Copy the lockfiles of the existing archive that the new one does not have
+/// at the same place. Lockfiles of the new archive win.
+fn merge_lockfiles(existing: &Path, root: &Path) -> io::Result<()> {
+    for lockfile in lockfiles_in(existing)? {
+        let Ok(relative) = lockfile.strip_prefix(existing) else {
+            continue;
+        };
+        let target = root.join(relative);
+        if target.exists() {
+            debug!(?target, "Keeping the new lockfile.");
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&lockfile, &target)?;
+    }
+    Ok(())
+}
+
+fn read_config(path: &Path) -> io::Result<toml::Table> {
+    if !path.is_file() {
+        return Ok(toml::Table::new());
+    }
+    toml::from_str(&fs::read_to_string(path)?).map_err(|err| {
+        error!(?err, ?path);
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    })
+}
+
+/// Merge the tables of `config` into `merged`, one level deep.
+fn merge_config(merged: &mut toml::Table, config: toml::Table) {
+    for (key, value) in config {
+        match (merged.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => {
+                for (name, definition) in value {
+                    existing.entry(name).or_insert(definition);
+                }
+            }
+            (Some(_), _) => {}
+            (None, value) => {
+                merged.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Name and version of a vendored crate.
+fn vendored_crate(dir: &Path) -> Option<(String, String)> {
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let manifest = toml::from_str::<toml::Table>(&manifest).ok()?;
+    let package = manifest.get("package")?;
+    Some((
+        package.get("name")?.as_str()?.to_string(),
+        package.get("version")?.as_str()?.to_string(),
+    ))
+}
+
+fn relative_vendor_dir(placement: &Path) -> String {
+    let depth = placement
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .count();
+    format!("{}vendor", "../".repeat(depth))
+}
+
+/// Move the vendored crates of both archives into one `vendor` directory at
+/// the top level and point the `.cargo/config.toml` next to every lockfile to
+/// it. Crates that are in none of the lockfiles are dropped.
+fn merge_vendor(existing: &Path, root: &Path) -> io::Result<()> {
+    let new_placements = placements(root)?;
+    let existing_placements = placements(existing)?;
+    merge_lockfiles(existing, root)?;
+
+    let staging = tempfile::Builder::new()
+        .prefix(".merged-vendor")
+        .rand_bytes(12)
+        .tempdir()?;
+    let mut config = toml::Table::new();
+    for (tree, tree_placements) in [(root, &new_placements), (existing, &existing_placements)] {
+        let dirs: BTreeSet<PathBuf> = std::iter::once(PathBuf::new())
+            .chain(tree_placements.iter().cloned())
+            .collect();
+        for dir in dirs {
+            merge_config(
+                &mut config,
+                read_config(&tree.join(&dir).join(".cargo").join("config.toml"))?,
+            );
+            let vendor_dir = tree.join(&dir).join("vendor");
+            if !vendor_dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&vendor_dir)? {
+                let crate_dir = entry?.path();
+                let Some(name) = crate_dir.file_name() else {
+                    continue;
+                };
+                let target = staging.path().join(name);
+                if !target.exists() {
+                    utils::copy_dir_all(&crate_dir, &target)?;
+                    continue;
+                }
+                let checksum = |dir: &Path| fs::read(dir.join(".cargo-checksum.json")).ok();
+                if checksum(&crate_dir) != checksum(&target) {
+                    let msg = format!(
+                        "Vendored crate {} differs between the archives. Set `versioned-dirs` to `true` for both.",
+                        name.to_string_lossy()
+                    );
+                    error!(msg);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+            }
+        }
+    }
+
+    let mut locked = BTreeSet::new();
+    for lockfile in lockfiles_in(root)? {
+        for package in load_lockfile(&lockfile)?.packages {
+            locked.insert((package.name.to_string(), package.version.to_string()));
+        }
+    }
+    let mut dropped = 0;
+    for entry in fs::read_dir(staging.path())? {
+        let crate_dir = entry?.path();
+        if vendored_crate(&crate_dir).is_some_and(|package| !locked.contains(&package)) {
+            debug!(
+                ?crate_dir,
+                "Dropping vendored crate that is in none of the lockfiles."
+            );
+            fs::remove_dir_all(&crate_dir)?;
+            dropped += 1;
+        }
+    }
+
+    for dir in std::iter::once(PathBuf::new()).chain(new_placements.iter().cloned()) {
+        let vendor_dir = root.join(&dir).join("vendor");
+        if vendor_dir.is_dir() {
+            fs::remove_dir_all(vendor_dir)?;
+        }
+        let config_file = root.join(&dir).join(".cargo").join("config.toml");
+        if config_file.is_file() {
+            fs::remove_file(config_file)?;
+        }
+    }
+    utils::copy_dir_all(staging.path(), &root.join("vendor"))?;
+
+    for placement in placements(root)? {
+        let mut vendored_sources = toml::Table::new();
+        vendored_sources.insert(
+            "directory".to_string(),
+            toml::Value::String(relative_vendor_dir(&placement)),
+        );
+        let mut placement_config = config.clone();
+        if let Some(toml::Value::Table(source)) = placement_config.get_mut("source") {
+            source.insert(
+                VENDORED_SOURCES.to_string(),
+                toml::Value::Table(vendored_sources),
+            );
+        }
+        let content = toml::to_string(&placement_config).map_err(|err| {
+            error!(?err);
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?;
+        let dot_cargo = root.join(&placement).join(".cargo");
+        fs::create_dir_all(&dot_cargo)?;
+        fs::write(dot_cargo.join("config.toml"), content)?;
+    }
+    info!(
+        "🧩 Merged the vendored crates into one vendor directory. Dropped {} crates that are in none of the lockfiles.",
+        dropped
+    );
+    Ok(())
+}
+
+/// Copy what the new archive lacks from the existing one. Index files of a
+/// local registry get the entries of both.
+fn copy_missing(existing: &Path, root: &Path, is_index: bool) -> io::Result<()> {
+    fs::create_dir_all(root)?;
+    for entry in fs::read_dir(existing)? {
+        let entry = entry?;
+        let path = entry.path();
+        let target = root.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            let is_index = is_index
+                || (entry.file_name() == "index"
+                    && existing
+                        .file_name()
+                        .is_some_and(|name| name == LOCAL_REGISTRY_DIR));
+            copy_missing(&path, &target, is_index)?;
+        } else if !target.exists() {
+            fs::copy(&path, &target)?;
+        } else if is_index {
+            let mut lines: BTreeSet<String> = fs::read_to_string(&target)?
+                .lines()
+                .map(ToString::to_string)
+                .collect();
+            lines.extend(fs::read_to_string(&path)?.lines().map(ToString::to_string));
+            let mut content = lines.into_iter().collect::<Vec<_>>().join("\n");
+            content.push('\n');
+            fs::write(&target, content)?;
+        }
+    }
+    Ok(())
+}
+
+fn merge_registry(existing: &Path, root: &Path) -> io::Result<()> {
+    merge_lockfiles(existing, root)?;
+    let existing_manifest = existing.join(MANIFEST_FILE_NAME);
+    if existing_manifest.is_file() {
+        fs::remove_file(existing_manifest)?;
+    }
+    copy_missing(existing, root, false)?;
+    let dot_cargo = root.join(".cargo");
+    if !dot_cargo.join(LOCAL_REGISTRY_DIR).is_dir() {
+        prune_registry(&dot_cargo, &lockfiles_in(root)?)?;
+    }
+    info!("🧩 Merged the registries.");
+    Ok(())
+}
+
+/// Merge the existing archive of `merge-into` into the directory that becomes
+/// the new archive. Both have to be created with the same method.
+pub fn merge_into_existing_archive(root: &Path, opts: &Opts) -> io::Result<()> {
+    let Some(merge_into) = &opts.merge_into else {
+        return Ok(());
+    };
+    let existing_archive = find_archive(opts, merge_into)?;
+    info!(?existing_archive, "🧩 Merging with the existing archive.");
+    let existing = extract_to_tempdir(&existing_archive)?;
+
+    let method = match opts.method {
+        Method::Registry => "registry",
+        Method::Vendor => "vendor",
+    };
+    let existing_method = method_of(existing.path())?;
+    if existing_method != method {
+        let msg = format!(
+            "Can't merge a {method} tarball into the {existing_method} tarball {}.",
+            existing_archive.display()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    match opts.method {
+        Method::Registry => merge_registry(existing.path(), root)?,
+        Method::Vendor => merge_vendor(existing.path(), root)?,
+    }
+    ArchiveManifest::new(root, &lockfiles_in(root)?, opts)?.write(root)
+}
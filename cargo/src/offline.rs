@@ -0,0 +1,281 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::decompress;
+use rustsec::cargo_lock::Package;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::alternate_registries::extend_cargo_home_config;
+use crate::audit::load_lockfile;
+use crate::cli::Method;
+use crate::cli::Opts;
+use crate::crate_cache::CrateCache;
+use crate::crate_cache::sha256_of;
+
+/// Name of the replacement source in the configuration of the temporary
+/// `CARGO_HOME`.
+pub const LOCAL_SOURCE_NAME: &str = "obs-service-cargo-local";
+/// Where a directory source built from `.crate` files is unpacked, relative to
+/// the temporary `CARGO_HOME`.
+const UNPACKED_SOURCES_DIR: &str = "obs-service-cargo-sources";
+
+/// A local replacement for crates.io.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalSource {
+    /// A cargo local registry with an `index` directory and `.crate` files.
+    LocalRegistry(PathBuf),
+    /// A cargo directory source of unpacked crates, e.g. the output of
+    /// `cargo vendor`.
+    Directory(PathBuf),
+    /// A directory of `.crate` files, either named `name-version.crate` or
+    /// after their checksum as in the crate cache.
+    CrateFiles(PathBuf),
+}
+
+impl LocalSource {
+    pub fn detect(path: &Path) -> io::Result<Self> {
+        if !path.is_dir() {
+            let msg = format!("Local registry {} is not a directory.", path.display());
+            error!(msg);
+            return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+        }
+        if path.join("index").is_dir() {
+            return Ok(LocalSource::LocalRegistry(path.to_path_buf()));
+        }
+        for entry in fs::read_dir(path)? {
+            if entry?.path().join(".cargo-checksum.json").is_file() {
+                return Ok(LocalSource::Directory(path.to_path_buf()));
+            }
+        }
+        Ok(LocalSource::CrateFiles(path.to_path_buf()))
+    }
+
+    /// The local source to use, if any. Offline mode without `local-registry`
+    /// uses the crate cache.
+    pub fn from_opts(opts: &Opts) -> io::Result<Option<Self>> {
+        match (&opts.local_registry, &opts.crate_cache) {
+            (Some(local_registry), _) => LocalSource::detect(local_registry).map(Some),
+            (None, Some(crate_cache)) if opts.offline => {
+                Ok(Some(LocalSource::CrateFiles(crate_cache.clone())))
+            }
+            (None, _) if opts.offline => {
+                let msg =
+                    "Offline mode needs `local-registry` or `crate-cache` to take crates from.";
+                error!(msg);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+            }
+            (None, _) => Ok(None),
+        }
+    }
+}
+
+/// Names and versions of the crates of a directory source.
+fn directory_crates(dir: &Path) -> io::Result<BTreeSet<(String, String)>> {
+    let mut crates = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let manifest = entry?.path().join("Cargo.toml");
+        if !manifest.is_file() {
+            continue;
+        }
+        let Ok(manifest) = toml::from_str::<toml::Table>(&fs::read_to_string(&manifest)?) else {
+            continue;
+        };
+        let package = manifest.get("package");
+        let name = package.and_then(|package| package.get("name"));
+        let version = package.and_then(|package| package.get("version"));
+        if let (Some(name), Some(version)) = (
+            name.and_then(toml::Value::as_str),
+            version.and_then(toml::Value::as_str),
+        ) {
+            crates.insert((name.to_string(), version.to_string()));
+        }
+    }
+    Ok(crates)
+}
+
+/// The `.crate` file of the package in a directory of `.crate` files.
+fn crate_file(dir: &Path, package: &Package) -> Option<PathBuf> {
+    let checksum = package.checksum.as_ref()?.to_string();
+    [
+        dir.join(format!("{}-{}.crate", package.name, package.version)),
+        CrateCache::crate_path_in(dir, &checksum),
+        dir.join(format!("{checksum}.crate")),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+}
+
+/// Unpack the `.crate` files of the packages into a directory source.
+/// Returns the packages that are missing or have a wrong checksum.
+fn unpack_crates(dir: &Path, packages: &[Package], target: &Path) -> io::Result<Vec<String>> {
+    fs::create_dir_all(target)?;
+    let mut problems = Vec::new();
+    for package in packages {
+        let Some(crate_file) = crate_file(dir, package) else {
+            problems.push(format!("{} {} is missing", package.name, package.version));
+            continue;
+        };
+        let checksum = sha256_of(&crate_file)?;
+        if package
+            .checksum
+            .as_ref()
+            .is_some_and(|expected| expected.to_string() != checksum)
+        {
+            problems.push(format!(
+                "{} {} has a wrong checksum in {}",
+                package.name,
+                package.version,
+                crate_file.display()
+            ));
+            continue;
+        }
+        decompress::targz(target, &crate_file)?;
+        let unpacked = target.join(format!("{}-{}", package.name, package.version));
+        fs::write(
+            unpacked.join(".cargo-checksum.json"),
+            format!("{{\"files\":{{}},\"package\":\"{checksum}\"}}"),
+        )?;
+    }
+    Ok(problems)
+}
+
+/// Fail before anything is extracted if offline mode or a local registry is
+/// combined with what can't use them. The registry method ships cargo's
+/// download cache, which a replaced crates.io source does not fill, and
+/// `cargo vendor-filterer` can't use a replaced source either.
+pub fn check_local_source_supported(opts: &Opts) -> io::Result<()> {
+    if !opts.offline && opts.local_registry.is_none() {
+        return Ok(());
+    }
+    let msg = if matches!(opts.method, Method::Registry) {
+        "Offline mode and `local-registry` are only supported by the vendor method. Use `method` vendor or vendor online."
+    } else if opts
+        .vendor_specific_args
+        .as_ref()
+        .unwrap_or_default()
+        .filter
+    {
+        "Offline mode and `local-registry` can't be used with `filter`. Set `filter` to false or vendor online."
+    } else {
+        return Ok(());
+    };
+    error!(msg);
+    Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+}
+
+/// Configure the temporary `CARGO_HOME` to replace crates.io with the local
+/// source. Every crate of the lockfiles that the local source lacks is
+/// reported before cargo runs. In offline mode, cargo itself is run with
+/// `--offline`. Returns whether a local source is used.
+pub fn configure_local_source(
+    cargo_home: &Path,
+    custom_root: &Path,
+    opts: &Opts,
+) -> io::Result<bool> {
+    let Some(local_source) = LocalSource::from_opts(opts)? else {
+        return Ok(false);
+    };
+    info!(?local_source, "🔌 Using a local source for crates.io.");
+    check_local_source_supported(opts)?;
+
+    let mut registry_packages = Vec::new();
+    let mut problems = Vec::new();
+    let mut has_lockfile = false;
+    for manifest in opts.manifests(custom_root) {
+        let lockfile = manifest.with_file_name("Cargo.lock");
+        if !lockfile.is_file() {
+            continue;
+        }
+        has_lockfile = true;
+        for package in load_lockfile(&lockfile)?.packages {
+            match &package.source {
+                Some(source) if source.is_default_registry() => registry_packages.push(package),
+                Some(source) if opts.offline => problems.push(format!(
+                    "{} {} from {} can't be fetched offline",
+                    package.name, package.version, source
+                )),
+                _ => {}
+            }
+        }
+    }
+    registry_packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    registry_packages.dedup_by(|a, b| a.name == b.name && a.version == b.version);
+
+    let (key, path) = match &local_source {
+        LocalSource::LocalRegistry(dir) => {
+            for package in &registry_packages {
+                if !dir
+                    .join(format!("{}-{}.crate", package.name, package.version))
+                    .is_file()
+                {
+                    problems.push(format!("{} {} is missing", package.name, package.version));
+                }
+            }
+            ("local-registry", dir.clone())
+        }
+        LocalSource::Directory(dir) => {
+            let available = directory_crates(dir)?;
+            for package in &registry_packages {
+                if !available.contains(&(package.name.to_string(), package.version.to_string())) {
+                    problems.push(format!("{} {} is missing", package.name, package.version));
+                }
+            }
+            ("directory", dir.clone())
+        }
+        LocalSource::CrateFiles(dir) => {
+            if !has_lockfile {
+                let msg = format!(
+                    "Crates from {} can only be used with a lockfile in the sources.",
+                    dir.display()
+                );
+                error!(msg);
+                return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+            }
+            let unpacked = cargo_home.join(UNPACKED_SOURCES_DIR);
+            problems.extend(unpack_crates(dir, &registry_packages, &unpacked)?);
+            ("directory", unpacked)
+        }
+    };
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("🛑 {}", problem);
+        }
+        let msg = format!(
+            "Found {} crates that can't be taken from the local source {}.",
+            problems.len(),
+            path.display()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+    }
+
+    let mut local = toml::Table::new();
+    local.insert(
+        key.to_string(),
+        toml::Value::String(path.canonicalize()?.to_string_lossy().to_string()),
+    );
+    let mut crates_io = toml::Table::new();
+    crates_io.insert(
+        "replace-with".to_string(),
+        toml::Value::String(LOCAL_SOURCE_NAME.to_string()),
+    );
+    let mut source = toml::Table::new();
+    source.insert("crates-io".to_string(), toml::Value::Table(crates_io));
+    source.insert(LOCAL_SOURCE_NAME.to_string(), toml::Value::Table(local));
+    let mut config = toml::Table::new();
+    config.insert("source".to_string(), toml::Value::Table(source));
+    extend_cargo_home_config(cargo_home, config)?;
+    Ok(true)
+}
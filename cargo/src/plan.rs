@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::Method;
+use crate::cli::Opts;
+use crate::toml_manifest::has_dependencies;
+use crate::toml_manifest::is_workspace;
+use crate::toml_manifest::workspace_has_dependencies;
+
+/// Directories that are not searched for manifests when the root manifest is
+/// missing.
+const SKIPPED_DIRS: &[&str] = &[".git", "target", "vendor"];
+/// How deep the sources are searched for manifests.
+const SEARCH_DEPTH: usize = 3;
+
+/// Why a manifest is vendored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestRole {
+    Root,
+    /// The first of `cargotoml`, used as the root manifest since there is
+    /// none at the custom root.
+    Fallback,
+    /// Another manifest of `cargotoml`.
+    Extra,
+}
+
+/// What would be done with one manifest.
+#[derive(Debug, Clone)]
+pub struct ManifestPlan {
+    pub role: ManifestRole,
+    /// Path relative to the top-level directory of the sources.
+    pub manifest: PathBuf,
+    pub is_workspace: bool,
+    pub has_dependencies: bool,
+    /// Path of the lockfile relative to the top-level directory of the
+    /// sources.
+    pub lockfile: PathBuf,
+    /// If not, it would be regenerated.
+    pub lockfile_exists: bool,
+    /// Whether `--locked` is passed to cargo.
+    pub locked: bool,
+}
+
+/// What vendoring would do, without running cargo.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub method: Method,
+    pub manifests: Vec<ManifestPlan>,
+    /// One line per update cargo would run.
+    pub updates: Vec<String>,
+    pub archive: PathBuf,
+    /// Misconfigurations that would make vendoring fail.
+    pub problems: Vec<String>,
+}
+
+fn relative(setup_workdir: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(setup_workdir)
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// Manifests in the sources, to suggest a `custom-root` or `cargotoml`.
+fn find_manifests(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            let skipped = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| SKIPPED_DIRS.contains(&name));
+            if depth > 0 && !skipped {
+                find_manifests(&path, depth - 1, found)?;
+            }
+        } else if entry.file_name() == "Cargo.toml" {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Describe one `update-crate` entry the way `cargo update` would apply it.
+fn describe_update(spec: &str, custom_root: &Path) -> Result<String, String> {
+    // NOTE: Both `name@version+manifest` and `name+manifest@version` work.
+    let (name, version, manifest) = match (spec.find('@'), spec.find('+')) {
+        (Some(at), Some(plus)) if at < plus => {
+            (&spec[..at], &spec[at + 1..plus], &spec[plus + 1..])
+        }
+        (Some(at), Some(plus)) => (&spec[..plus], &spec[at + 1..], &spec[plus + 1..at]),
+        (Some(at), None) => (&spec[..at], &spec[at + 1..], ""),
+        (None, Some(plus)) => (&spec[..plus], "", &spec[plus + 1..]),
+        (None, None) => (spec, "", ""),
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("`{spec}` has no crate name."));
+    }
+    let action = match version.trim() {
+        "" => "to the latest compatible version".to_string(),
+        "recursive" => "recursively".to_string(),
+        version if semver::Version::parse(version).is_ok() => format!("to exactly {version}"),
+        version => {
+            return Err(format!(
+                "`{spec}`: expected a version or `recursive`. Got `{version}`."
+            ));
+        }
+    };
+    let manifest = manifest.trim();
+    if manifest.is_empty() {
+        return Ok(format!("update {name} {action} in the root manifest"));
+    }
+    if !manifest.ends_with("Cargo.toml") {
+        return Err(format!(
+            "`{spec}`: expected a manifest filename. Got `{manifest}`."
+        ));
+    }
+    if !custom_root.join(manifest).is_file() {
+        return Err(format!("`{spec}`: manifest `{manifest}` does not exist."));
+    }
+    Ok(format!("update {name} {action} in {manifest}"))
+}
+
+impl Plan {
+    /// Analyse the manifests of the extracted sources.
+    pub fn new(setup_workdir: &Path, custom_root: &Path, opts: &Opts) -> io::Result<Self> {
+        let filter = opts
+            .vendor_specific_args
+            .as_ref()
+            .is_some_and(|args| args.filter);
+        let mut problems = Vec::new();
+        let mut manifests: Vec<(ManifestRole, PathBuf)> = Vec::new();
+
+        let root_manifest = custom_root.join("Cargo.toml");
+        let skip_root =
+            matches!(opts.method, Method::Registry) && opts.no_root_manifest.unwrap_or_default();
+        let mut extra = opts.manifest_path.iter();
+        if skip_root {
+            debug!("Root manifest is skipped with `no-root-manifest`.");
+        } else if root_manifest.is_file() {
+            manifests.push((ManifestRole::Root, root_manifest));
+        } else if matches!(opts.method, Method::Vendor)
+            && let Some(first) = extra.next()
+        {
+            manifests.push((ManifestRole::Fallback, custom_root.join(first)));
+        } else {
+            let mut found = Vec::new();
+            find_manifests(setup_workdir, SEARCH_DEPTH, &mut found)?;
+            found.sort();
+            problems.push(format!(
+                "There is no root manifest at {} and no `cargotoml` to fall back to. Manifests in the sources: [{}].",
+                relative(setup_workdir, &root_manifest).display(),
+                found
+                    .iter()
+                    .map(|manifest| relative(setup_workdir, manifest).display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        manifests.extend(extra.map(|manifest| (ManifestRole::Extra, custom_root.join(manifest))));
+
+        let mut manifest_plans = Vec::new();
+        for (role, manifest) in manifests {
+            let shown = relative(setup_workdir, &manifest);
+            if !manifest.is_file() {
+                problems.push(format!("Manifest {} does not exist.", shown.display()));
+                continue;
+            }
+            let is_workspace = is_workspace(&manifest)?;
+            let has_deps = match has_dependencies(&manifest) {
+                Ok(has_deps) => has_deps,
+                Err(err) => {
+                    problems.push(format!("Manifest {}: {}", shown.display(), err));
+                    continue;
+                }
+            };
+            let workspace_has_deps = if is_workspace {
+                match workspace_has_dependencies(custom_root, &manifest) {
+                    Ok(has_deps) => has_deps,
+                    Err(err) => {
+                        problems.push(format!("Workspace {}: {}", shown.display(), err));
+                        false
+                    }
+                }
+            } else {
+                false
+            };
+            let lockfile = manifest.with_file_name("Cargo.lock");
+            let lockfile_exists = lockfile.is_file();
+            manifest_plans.push(ManifestPlan {
+                role,
+                manifest: shown,
+                is_workspace,
+                has_dependencies: has_deps || workspace_has_deps,
+                lockfile: relative(setup_workdir, &lockfile),
+                lockfile_exists,
+                // NOTE: The vendor filterer can't verify lockfiles.
+                locked: lockfile_exists
+                    && opts.respect_lockfile
+                    && !(matches!(opts.method, Method::Vendor) && filter),
+            });
+        }
+
+        let mut updates = Vec::new();
+        if opts.update {
+            updates.push("update every dependency of every lockfile".to_string());
+        } else {
+            for spec in &opts.update_crate {
+                match describe_update(spec, custom_root) {
+                    Ok(update) => updates.push(update),
+                    Err(problem) => problems.push(problem),
+                }
+            }
+        }
+
+        Ok(Plan {
+            method: opts.method.clone(),
+            manifests: manifest_plans,
+            updates,
+            archive: opts.outdir.join(opts.archive_name(&opts.compression)?),
+            problems,
+        })
+    }
+
+    pub fn show(&self) {
+        let method = match self.method {
+            Method::Registry => "registry",
+            Method::Vendor => "vendor",
+        };
+        info!("🗺️ Plan of the {} method:", method);
+        for manifest in &self.manifests {
+            let role = match manifest.role {
+                ManifestRole::Root => "root manifest",
+                ManifestRole::Fallback => "root manifest (fallback to the first `cargotoml`)",
+                ManifestRole::Extra => "other manifest",
+            };
+            info!(
+                "🗺️ {}: {}{}{}",
+                role,
+                manifest.manifest.display(),
+                if manifest.is_workspace {
+                    ", workspace"
+                } else {
+                    ""
+                },
+                if manifest.has_dependencies {
+                    ""
+                } else {
+                    ", no dependencies"
+                }
+            );
+            if manifest.lockfile_exists {
+                info!(
+                    "🗺️   lockfile {} exists, `--locked` {}",
+                    manifest.lockfile.display(),
+                    if manifest.locked {
+                        "is applied"
+                    } else {
+                        "is not applied"
+                    }
+                );
+            } else {
+                info!(
+                    "🗺️   lockfile {} would be regenerated",
+                    manifest.lockfile.display()
+                );
+            }
+        }
+        if self.updates.is_empty() {
+            info!("🗺️ No dependencies would be updated.");
+        }
+        for update in &self.updates {
+            info!("🗺️ Would {}.", update);
+        }
+        info!("🗺️ Would write {}", self.archive.display());
+        for problem in &self.problems {
+            error!("🛑 {}", problem);
+        }
+    }
+}
+
+/// Show the plan instead of vendoring. Fails if vendoring would fail because
+/// of the configuration.
+pub fn dry_run(setup_workdir: &Path, custom_root: &Path, opts: &Opts) -> io::Result<()> {
+    let plan = Plan::new(setup_workdir, custom_root, opts)?;
+    plan.show();
+    if !plan.problems.is_empty() {
+        let msg = format!(
+            "Dry run found {} problems in the configuration.",
+            plan.problems.len()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    info!("🗺️ Dry run finished. Nothing was vendored.");
+    Ok(())
+}
@@ -0,0 +1,501 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use rustsec::SourceId;
+use semver::Version;
+use semver::VersionReq;
+use serde::Deserialize;
+use serde::Serialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::alternate_registries::AlternateRegistry;
+use crate::audit::load_lockfile;
+use crate::bans::BannedCrate;
+use crate::bans::DuplicateCrate;
+use crate::bans::check_bans;
+use crate::bans::report_bans;
+use crate::cli::LintLevel;
+use crate::cli::Opts;
+use crate::git_deps::GitDependency;
+use crate::git_deps::check_git_dependencies;
+use crate::git_deps::collect_git_dependencies;
+use crate::preflight::local_manifests;
+use crate::toml_manifest::DependencyReference;
+use crate::toml_manifest::dependency_references;
+use crate::vet::VetFinding;
+use crate::vet::check_vet;
+use crate::vet::report_vet;
+
+/// Alias for the crates.io registry, whether it's accessed through the git
+/// or the sparse index.
+pub const CRATES_IO_ALIASES: &[&str] = &["crates-io", "crates.io"];
+
+/// Policy file given through `--policy`. The sections follow the layout of
+/// cargo-deny's `deny.toml`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub sources: SourcesPolicy,
+    #[serde(default)]
+    pub bans: BansPolicy,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SourcesPolicy {
+    /// Registries crates can come from, as index URLs or `crates-io`.
+    /// Defaults to crates.io only.
+    #[serde(default)]
+    pub allow_registry: Vec<String>,
+    /// URL prefixes of git repositories crates can come from.
+    #[serde(default)]
+    pub allow_git: Vec<String>,
+    /// Hosts git repositories can be fetched from.
+    #[serde(default)]
+    pub allow_git_hosts: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BansPolicy {
+    /// What to do with crates resolved at more than one version.
+    #[serde(default)]
+    pub multiple_versions: LintLevel,
+    /// Crates that must not be part of the dependency tree.
+    #[serde(default)]
+    pub deny: Vec<CrateSpec>,
+    /// Crates that are not counted when looking for multiple versions.
+    #[serde(default)]
+    pub skip: Vec<CrateSpec>,
+}
+
+/// A crate name with an optional version requirement. In the policy file it
+/// is either a string like `name` or `name@<1.2`, or a table with `crate`, or
+/// `name` and `version`, and an optional `reason`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawCrateSpec")]
+pub struct CrateSpec {
+    pub name: String,
+    pub version: Option<VersionReq>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawCrateSpec {
+    Spec(String),
+    Table(RawCrateTable),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCrateTable {
+    #[serde(rename = "crate")]
+    krate: Option<String>,
+    name: Option<String>,
+    version: Option<String>,
+    reason: Option<String>,
+}
+
+impl TryFrom<RawCrateSpec> for CrateSpec {
+    type Error = String;
+
+    fn try_from(raw: RawCrateSpec) -> Result<Self, Self::Error> {
+        match raw {
+            RawCrateSpec::Spec(spec) => spec.parse(),
+            RawCrateSpec::Table(table) => {
+                let mut spec: CrateSpec = match (table.krate, table.name) {
+                    (Some(spec), None) => spec.parse()?,
+                    (None, Some(name)) => CrateSpec {
+                        name,
+                        version: None,
+                        reason: None,
+                    },
+                    _ => return Err("expected either `crate` or `name`".to_string()),
+                };
+                if let Some(version) = table.version {
+                    if spec.version.is_some() {
+                        return Err(format!("`{}` has a version requirement twice", spec.name));
+                    }
+                    spec.version = Some(
+                        VersionReq::parse(&version)
+                            .map_err(|err| format!("invalid version `{version}`: {err}"))?,
+                    );
+                }
+                spec.reason = table.reason;
+                Ok(spec)
+            }
+        }
+    }
+}
+
+impl FromStr for CrateSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, version) = match spec.split_once('@') {
+            Some((name, version)) => (
+                name,
+                Some(
+                    VersionReq::parse(version)
+                        .map_err(|err| format!("invalid version `{version}`: {err}"))?,
+                ),
+            ),
+            None => (spec, None),
+        };
+        if name.is_empty() {
+            return Err(format!("missing crate name in `{spec}`"));
+        }
+        Ok(CrateSpec {
+            name: name.to_string(),
+            version,
+            reason: None,
+        })
+    }
+}
+
+impl fmt::Display for CrateSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{}@{}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl CrateSpec {
+    pub fn matches(&self, name: &str, version: &Version) -> bool {
+        self.name == name
+            && self
+                .version
+                .as_ref()
+                .is_none_or(|requirement| requirement.matches(version))
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path).inspect_err(|err| {
+            error!(?err, ?path, "Failed to read policy file.");
+        })?;
+        toml::from_str(&content).map_err(|err| {
+            let msg = format!("Invalid policy file {}: {}", path.display(), err);
+            error!(msg);
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })
+    }
+
+    /// Extend the bans with the ones of another policy. The allowlists are
+    /// left alone so another policy can't widen them.
+    pub fn merge_bans(&mut self, other: Policy) {
+        self.bans.deny.extend(other.bans.deny);
+        self.bans.skip.extend(other.bans.skip);
+    }
+
+    /// The policy file, if any, merged with the policy parameters and the trusted
+    /// upstream configuration.
+    pub fn from_opts(opts: &Opts) -> io::Result<Self> {
+        let mut policy = match &opts.policy {
+            Some(path) => {
+                info!(?path, "📜 Loading policy file.");
+                Self::load(path)?
+            }
+            None => Self::default(),
+        };
+        policy
+            .sources
+            .allow_registry
+            .extend(opts.allowed_registry.iter().cloned());
+        policy
+            .sources
+            .allow_git
+            .extend(opts.allowed_git.iter().cloned());
+        policy
+            .sources
+            .allow_git_hosts
+            .extend(opts.allowed_git_host.iter().cloned());
+        if let Some(upstream) = &opts.upstream_policy {
+            policy.merge_bans(upstream.clone());
+        }
+        for spec in &opts.deny_crate {
+            let spec = spec.parse().map_err(|err| {
+                let msg = format!("Invalid crate `{spec}` for `--deny-crate`: {err}");
+                error!(msg);
+                io::Error::new(io::ErrorKind::InvalidInput, msg)
+            })?;
+            policy.bans.deny.push(spec);
+        }
+        if let Some(multiple_versions) = opts.multiple_versions {
+            policy.bans.multiple_versions = multiple_versions;
+        }
+        if policy.sources.allow_registry.is_empty() {
+            policy
+                .sources
+                .allow_registry
+                .push(CRATES_IO_ALIASES[0].to_string());
+            // NOTE: Registries that are configured explicitly are trusted
+            // unless the allowed registries are set.
+            policy.sources.allow_registry.extend(
+                AlternateRegistry::from_opts(opts)?
+                    .into_iter()
+                    .map(|registry| registry.index),
+            );
+        }
+        debug!(?policy);
+        Ok(policy)
+    }
+}
+
+fn normalize_url(url: &str) -> &str {
+    let url = url.strip_prefix("sparse+").unwrap_or(url);
+    let url = url.strip_prefix("registry+").unwrap_or(url);
+    let url = url.strip_prefix("git+").unwrap_or(url);
+    url.trim_end_matches('/')
+}
+
+/// Whether `url` is `allowed` or lies below it. A look-alike such as
+/// `https://github.com/rust-lang-evil` is not below `https://github.com/rust-lang/`.
+fn is_below(url: &str, allowed: &str) -> bool {
+    let allowed = normalize_url(allowed);
+    url.strip_prefix(allowed)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl SourcesPolicy {
+    pub fn allows(&self, source: &SourceId) -> bool {
+        let url = normalize_url(source.url().as_str());
+        if source.is_git() {
+            let unrestricted = self.allow_git.is_empty() && self.allow_git_hosts.is_empty();
+            let host = source.url().host_str().unwrap_or_default();
+            unrestricted
+                || self.allow_git.iter().any(|allowed| is_below(url, allowed))
+                || self.allow_git_hosts.iter().any(|allowed| allowed == host)
+        } else if source.is_path() {
+            true
+        } else {
+            self.allow_registry.iter().any(|allowed| {
+                if CRATES_IO_ALIASES.contains(&allowed.as_str()) {
+                    source.is_default_registry()
+                } else {
+                    normalize_url(allowed) == url
+                }
+            })
+        }
+    }
+}
+
+/// A package resolved from a source that is not allowed by the policy.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceViolation {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    pub lockfile: PathBuf,
+    pub declared_in: Option<(PathBuf, usize)>,
+}
+
+fn find_declaration(
+    setup_workdir: &Path,
+    references: &[DependencyReference],
+    name: &str,
+) -> Option<(PathBuf, usize)> {
+    references
+        .iter()
+        .filter(|reference| reference.field("package").unwrap_or(&reference.name) == name)
+        .find_map(|reference| {
+            let key = ["registry", "git", "registry-index"]
+                .into_iter()
+                .find(|key| reference.field(key).is_some())?;
+            let manifest = reference
+                .manifest
+                .strip_prefix(setup_workdir)
+                .unwrap_or(&reference.manifest)
+                .to_path_buf();
+            Some((manifest, reference.line_of(key).unwrap_or(1)))
+        })
+}
+
+/// Check the source of every package in the lockfiles against the policy.
+pub fn check_sources(
+    setup_workdir: &Path,
+    lockfiles: &[impl AsRef<Path>],
+    manifests: &[impl AsRef<Path>],
+    sources: &SourcesPolicy,
+) -> io::Result<Vec<SourceViolation>> {
+    let mut references = Vec::new();
+    for manifest in manifests {
+        references.extend(dependency_references(manifest.as_ref())?);
+    }
+    let mut violations = Vec::new();
+    for lockfile_path in lockfiles {
+        let lockfile_path = lockfile_path.as_ref();
+        let lockfile = load_lockfile(lockfile_path)?;
+        for package in lockfile.packages {
+            let Some(source) = &package.source else {
+                continue;
+            };
+            if sources.allows(source) {
+                continue;
+            }
+            violations.push(SourceViolation {
+                name: package.name.to_string(),
+                version: package.version.to_string(),
+                source: source.url().to_string(),
+                lockfile: lockfile_path
+                    .strip_prefix(setup_workdir)
+                    .unwrap_or(lockfile_path)
+                    .to_path_buf(),
+                declared_in: find_declaration(setup_workdir, &references, package.name.as_str()),
+            });
+        }
+    }
+    Ok(violations)
+}
+
+/// Results of the policy checks written to `--policy-report`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct PolicyReport<'a> {
+    git_dependencies: Vec<GitDependencyReport<'a>>,
+    source_violations: &'a [SourceViolation],
+    banned: &'a [BannedCrate],
+    multiple_versions: LintLevel,
+    duplicates: &'a [DuplicateCrate],
+    vet: Option<&'a [VetFinding]>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct GitDependencyReport<'a> {
+    name: &'a str,
+    version: &'a str,
+    url: &'a str,
+    reference: String,
+    commit: Option<&'a str>,
+    floating: bool,
+}
+
+impl<'a> From<&'a GitDependency> for GitDependencyReport<'a> {
+    fn from(git_dependency: &'a GitDependency) -> Self {
+        GitDependencyReport {
+            name: &git_dependency.name,
+            version: &git_dependency.version,
+            url: git_dependency.url.as_str(),
+            reference: git_dependency.requested_ref(),
+            commit: git_dependency.commit.as_deref(),
+            floating: git_dependency.is_floating(),
+        }
+    }
+}
+
+fn write_report(path: &Path, report: &PolicyReport) -> io::Result<()> {
+    let content = serde_json::to_string_pretty(report).map_err(|err| {
+        error!(?err, "Failed to serialize policy report.");
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    })?;
+    fs::write(path, content).inspect_err(|err| {
+        error!(?err, ?path, "Failed to write policy report.");
+    })?;
+    info!(?path, "📜 Wrote policy report.");
+    Ok(())
+}
+
+/// Run every policy check against the lockfiles collected by either method.
+/// All checks run before failing so the log and the report are complete.
+pub fn perform_policy_checks(
+    setup_workdir: &Path,
+    custom_root: &Path,
+    lockfiles: &[impl AsRef<Path>],
+    opts: &Opts,
+) -> io::Result<()> {
+    info!("📜 Checking dependencies against the policy...");
+    let policy = Policy::from_opts(opts)?;
+    let setup_workdir = setup_workdir
+        .canonicalize()
+        .unwrap_or(setup_workdir.to_path_buf());
+    let manifests = local_manifests(&setup_workdir, custom_root, &opts.manifests(custom_root))?;
+
+    let git_dependencies = collect_git_dependencies(&setup_workdir, lockfiles, &manifests)?;
+    let git_result = check_git_dependencies(&git_dependencies, opts.deny_floating_git_refs);
+
+    let violations = check_sources(&setup_workdir, lockfiles, &manifests, &policy.sources)?;
+    for violation in &violations {
+        match &violation.declared_in {
+            Some((manifest, line)) => error!(
+                "🛑 {} {} comes from `{}` which is not an allowed source. Declared at {}:{}.",
+                violation.name,
+                violation.version,
+                violation.source,
+                manifest.display(),
+                line
+            ),
+            None => error!(
+                "🛑 {} {} comes from `{}` which is not an allowed source. Pulled in transitively by {} according to {}.",
+                violation.name,
+                violation.version,
+                violation.source,
+                violation.lockfile.with_file_name("Cargo.toml").display(),
+                violation.lockfile.display()
+            ),
+        }
+    }
+
+    let (banned, duplicates) = check_bans(&setup_workdir, lockfiles, &policy.bans)?;
+    let denied_bans = report_bans(&banned, &duplicates, policy.bans.multiple_versions);
+
+    let vet_findings = check_vet(custom_root, lockfiles, opts)?;
+    let denied_vet = vet_findings
+        .as_deref()
+        .map(|findings| report_vet(findings, opts.cargo_vet))
+        .unwrap_or_default();
+
+    if let Some(path) = &opts.policy_report {
+        write_report(
+            path,
+            &PolicyReport {
+                git_dependencies: git_dependencies.iter().map(Into::into).collect(),
+                source_violations: &violations,
+                banned: &banned,
+                multiple_versions: policy.bans.multiple_versions,
+                duplicates: &duplicates,
+                vet: vet_findings.as_deref(),
+            },
+        )?;
+    }
+
+    git_result?;
+    if !violations.is_empty() {
+        let msg = format!(
+            "Found {} dependencies from sources that are not allowed.",
+            violations.len()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    if denied_bans > 0 {
+        let msg = format!("Found {denied_bans} crates that are denied by the bans policy.");
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    if denied_vet > 0 {
+        let msg = format!("Found {denied_vet} crates that are not vetted as required.");
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    info!("📜 All dependencies follow the policy.");
+    Ok(())
+}
@@ -0,0 +1,312 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use glob::glob;
+use toml::de::DeTable;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::toml_manifest::dependency_references;
+use crate::toml_manifest::get;
+use crate::toml_manifest::get_table;
+use crate::toml_manifest::line_of;
+
+/// A `path = "..."` found in a manifest, either in a dependency table
+/// or in a `[patch]` or `[replace]` entry.
+#[derive(Debug, Clone)]
+pub struct PathReference {
+    pub manifest: PathBuf,
+    pub line: usize,
+    pub table: String,
+    pub name: String,
+    pub path: PathBuf,
+    pub resolved: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathIssueKind {
+    Missing,
+    MissingSubmodule(PathBuf),
+    NoManifest,
+    OutsideSourceTree,
+    OutsideCustomRoot,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathIssue {
+    pub reference: PathReference,
+    pub kind: PathIssueKind,
+}
+
+impl PathIssue {
+    /// Only a target outside the custom root can still be built by cargo, as
+    /// long as it is part of the extracted sources.
+    pub fn is_fatal(&self) -> bool {
+        self.kind != PathIssueKind::OutsideCustomRoot
+    }
+}
+
+impl fmt::Display for PathIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reference = &self.reference;
+        write!(
+            f,
+            "{}:{}: `{}` in `[{}]` points to `{}`",
+            reference.manifest.display(),
+            reference.line,
+            reference.name,
+            reference.table,
+            reference.path.display()
+        )?;
+        match &self.kind {
+            PathIssueKind::Missing => write!(f, " which does not exist in the sources"),
+            PathIssueKind::MissingSubmodule(submodule) => write!(
+                f,
+                " which is inside the git submodule `{}` that is missing from the sources",
+                submodule.display()
+            ),
+            PathIssueKind::NoManifest => write!(f, " which does not contain a `Cargo.toml`"),
+            PathIssueKind::OutsideSourceTree => {
+                write!(f, " which is outside of the extracted sources")
+            }
+            PathIssueKind::OutsideCustomRoot => write!(f, " which is outside of the custom root"),
+        }
+    }
+}
+
+/// Lexically resolve `.` and `..` since the target may not exist.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Collect every `path` found in the dependency tables, `[patch]` and
+/// `[replace]` of a manifest. Paths are resolved relative to the manifest.
+pub fn collect_path_references(manifest: &Path) -> io::Result<Vec<PathReference>> {
+    let base = manifest.parent().unwrap_or(Path::new("."));
+    let references = dependency_references(manifest)?
+        .into_iter()
+        .filter_map(|reference| {
+            let raw_path = reference.field("path")?;
+            Some(PathReference {
+                line: reference.line_of("path").unwrap_or(1),
+                path: PathBuf::from(raw_path),
+                resolved: normalize(&base.join(raw_path)),
+                manifest: reference.manifest,
+                table: reference.table,
+                name: reference.name,
+            })
+        })
+        .collect();
+    debug!(?manifest, ?references, "Collected path references");
+    Ok(references)
+}
+
+/// Member manifests of a workspace. Explicit members that do not exist are
+/// returned as references so they are reported like any other missing path.
+fn collect_workspace_members(
+    manifest: &Path,
+    references: &mut Vec<PathReference>,
+) -> io::Result<Vec<PathBuf>> {
+    let text = fs::read_to_string(manifest)?;
+    let Ok(document) = DeTable::parse(&text) else {
+        return Ok(Vec::new());
+    };
+    let base = manifest.parent().unwrap_or(Path::new("."));
+    let mut member_manifests = Vec::new();
+    let Some(members) =
+        get_table(document.get_ref(), "workspace").and_then(|workspace| get(workspace, "members"))
+    else {
+        return Ok(member_manifests);
+    };
+    let Some(members) = members.get_ref().as_array() else {
+        return Ok(member_manifests);
+    };
+    for member in members.iter() {
+        let Some(raw_member) = member.get_ref().as_str() else {
+            continue;
+        };
+        let member_path = base.join(raw_member);
+        let is_glob = raw_member.contains(['*', '?', '[']);
+        if is_glob {
+            let matches = glob(&member_path.to_string_lossy()).map_err(|err| {
+                error!(?err);
+                io::Error::new(io::ErrorKind::InvalidInput, "Invalid glob pattern")
+            })?;
+            for matched in matches.flatten() {
+                if matched.join("Cargo.toml").is_file() {
+                    member_manifests.push(matched.join("Cargo.toml"));
+                }
+            }
+        } else if member_path.join("Cargo.toml").is_file() {
+            member_manifests.push(member_path.join("Cargo.toml"));
+        } else {
+            references.push(PathReference {
+                manifest: manifest.to_path_buf(),
+                line: line_of(&text, member.span().start),
+                table: "workspace.members".to_string(),
+                name: raw_member.to_string(),
+                path: PathBuf::from(raw_member),
+                resolved: normalize(&member_path),
+            });
+        }
+    }
+    Ok(member_manifests)
+}
+
+fn submodule_paths(setup_workdir: &Path) -> Vec<PathBuf> {
+    let Ok(gitmodules) = fs::read_to_string(setup_workdir.join(".gitmodules")) else {
+        return Vec::new();
+    };
+    gitmodules
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "path").then(|| setup_workdir.join(value.trim()))
+        })
+        .collect()
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(false)
+}
+
+/// Walk all manifests reachable from the given ones through workspace
+/// members and path dependencies, classifying every path on the way.
+fn walk_manifests(
+    setup_workdir: &Path,
+    custom_root: &Path,
+    manifests: &[PathBuf],
+) -> io::Result<(Vec<PathBuf>, Vec<PathIssue>)> {
+    let setup_workdir = setup_workdir
+        .canonicalize()
+        .unwrap_or(setup_workdir.to_path_buf());
+    let custom_root = custom_root
+        .canonicalize()
+        .unwrap_or(custom_root.to_path_buf());
+    let submodules = submodule_paths(&setup_workdir);
+    let mut issues = Vec::new();
+    let mut visited: Vec<PathBuf> = Vec::new();
+    let mut seen: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut queue: VecDeque<PathBuf> = manifests
+        .iter()
+        .filter(|manifest| manifest.is_file())
+        .cloned()
+        .collect();
+
+    while let Some(manifest) = queue.pop_front() {
+        let manifest = manifest.canonicalize()?;
+        if !seen.insert(manifest.clone()) {
+            continue;
+        }
+        visited.push(manifest.clone());
+        let mut references = collect_path_references(&manifest)?;
+        queue.extend(collect_workspace_members(&manifest, &mut references)?);
+
+        for mut reference in references {
+            if let Ok(stripped) = reference.manifest.strip_prefix(&setup_workdir) {
+                reference.manifest = stripped.to_path_buf();
+            }
+            let target = reference
+                .resolved
+                .canonicalize()
+                .unwrap_or(reference.resolved.clone());
+            let kind = if !target.starts_with(&setup_workdir) {
+                Some(PathIssueKind::OutsideSourceTree)
+            } else if let Some(submodule) = submodules.iter().find(|submodule| {
+                target.starts_with(submodule) && (!target.exists() || is_empty_dir(&target))
+            }) {
+                let submodule = submodule
+                    .strip_prefix(&setup_workdir)
+                    .unwrap_or(submodule)
+                    .to_path_buf();
+                Some(PathIssueKind::MissingSubmodule(submodule))
+            } else if !target.exists() {
+                Some(PathIssueKind::Missing)
+            } else if !target.join("Cargo.toml").is_file() {
+                Some(PathIssueKind::NoManifest)
+            } else {
+                queue.push_back(target.join("Cargo.toml"));
+                (!target.starts_with(&custom_root)).then_some(PathIssueKind::OutsideCustomRoot)
+            };
+            if let Some(kind) = kind {
+                issues.push(PathIssue { reference, kind });
+            }
+        }
+    }
+    Ok((visited, issues))
+}
+
+/// Resolve every path dependency, `[patch]` and `[replace]` entry reachable
+/// from the given manifests and report the ones cargo won't be able to load.
+pub fn analyse_path_dependencies(
+    setup_workdir: &Path,
+    custom_root: &Path,
+    manifests: &[PathBuf],
+) -> io::Result<Vec<PathIssue>> {
+    walk_manifests(setup_workdir, custom_root, manifests).map(|(_, issues)| issues)
+}
+
+/// Every manifest of the sources reachable from the given ones, including
+/// workspace members and local path dependencies.
+pub fn local_manifests(
+    setup_workdir: &Path,
+    custom_root: &Path,
+    manifests: &[PathBuf],
+) -> io::Result<Vec<PathBuf>> {
+    walk_manifests(setup_workdir, custom_root, manifests).map(|(manifests, _)| manifests)
+}
+
+/// Pre-flight check that runs before any cargo command. Fails if any path
+/// dependency or patch points to something cargo can't load.
+pub fn check_path_dependencies(
+    setup_workdir: &Path,
+    custom_root: &Path,
+    manifests: &[PathBuf],
+) -> io::Result<()> {
+    info!("🛫 Checking path dependencies and patches of all manifests...");
+    let issues = analyse_path_dependencies(setup_workdir, custom_root, manifests)?;
+    let mut fatal = 0;
+    for issue in &issues {
+        if issue.is_fatal() {
+            fatal += 1;
+            error!("🛑 {}", issue);
+        } else {
+            warn!("⚠️ {}", issue);
+        }
+    }
+    if fatal > 0 {
+        let msg = format!(
+            "Found {fatal} path dependencies or patches that cargo can't load. Check if the source tarball contains them or if `custom-root` is set properly."
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+    }
+    info!("🛬 All path dependencies and patches resolved.");
+    Ok(())
+}
@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::utils;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+use crate::cargo_commands::cargo_metadata_offline;
+use crate::dependency_changes::extract_to_tempdir;
+use crate::dependency_changes::find_lockfiles;
+
+/// What was removed from the registry of a cargo home.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneSummary {
+    /// `.crate` files of crates that are in none of the lockfiles.
+    pub crates: Vec<PathBuf>,
+    /// Cached index entries of crates that are in none of the lockfiles.
+    pub index_entries: Vec<PathBuf>,
+    /// Git databases without any revision of the lockfiles.
+    pub git_databases: Vec<PathBuf>,
+    /// Revisions of git dependencies kept in the git databases.
+    pub git_revisions: usize,
+    /// Size of the removed files in bytes.
+    pub bytes: u64,
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+fn git(dir: &Path, args: &[&str]) -> io::Result<String> {
+    let output = std::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()?;
+    if !output.status.success() {
+        let stderrput = String::from_utf8_lossy(&output.stderr);
+        debug!(?stderrput, ?args, ?dir);
+        return Err(io::Error::new(io::ErrorKind::Interrupted, stderrput));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Reduce a git database to the revisions of the lockfiles by making it a
+/// shallow repository and dropping everything else. Databases without any of
+/// the revisions are removed.
+fn shrink_git_db(
+    db: &Path,
+    revisions: &BTreeSet<String>,
+    summary: &mut PruneSummary,
+) -> io::Result<()> {
+    let kept: Vec<&str> = revisions
+        .iter()
+        .filter(|revision| git(db, &["cat-file", "-e", &format!("{revision}^{{commit}}")]).is_ok())
+        .map(String::as_str)
+        .collect();
+    if kept.is_empty() {
+        fs::remove_dir_all(db)?;
+        summary.git_databases.push(db.to_path_buf());
+        return Ok(());
+    }
+    for refname in git(db, &["for-each-ref", "--format=%(refname)"])?.lines() {
+        git(db, &["update-ref", "-d", refname])?;
+    }
+    // NOTE: Cargo fetches locked revisions into the same refs.
+    for revision in &kept {
+        git(
+            db,
+            &["update-ref", &format!("refs/commit/{revision}"), revision],
+        )?;
+    }
+    fs::write(db.join("shallow"), format!("{}\n", kept.join("\n")))?;
+    let fetch_head = db.join("FETCH_HEAD");
+    if fetch_head.exists() {
+        fs::remove_file(fetch_head)?;
+    }
+    git(db, &["reflog", "expire", "--expire=now", "--all"])?;
+    // A single thread and no bitmaps keep the pack reproducible.
+    git(
+        db,
+        &[
+            "-c",
+            "pack.threads=1",
+            "-c",
+            "repack.writeBitmaps=false",
+            "gc",
+            "--prune=now",
+            "--quiet",
+        ],
+    )?;
+    summary.git_revisions += kept.len();
+    Ok(())
+}
+
+fn remove_file(path: &Path, summary: &mut PruneSummary) -> io::Result<()> {
+    summary.bytes += fs::metadata(path)?.len();
+    fs::remove_file(path)
+}
+
+/// Remove the files of a cached index that are not in `keep` and the
+/// directories that become empty. Returns whether the directory is empty.
+fn prune_index_cache(
+    dir: &Path,
+    keep: &BTreeSet<String>,
+    summary: &mut PruneSummary,
+) -> io::Result<bool> {
+    let mut is_empty = true;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if prune_index_cache(&path, keep, summary)? {
+                fs::remove_dir(&path)?;
+            } else {
+                is_empty = false;
+            }
+        } else if keep.contains(&entry.file_name().to_string_lossy().to_string()) {
+            is_empty = false;
+        } else {
+            remove_file(&path, summary)?;
+            summary.index_entries.push(path);
+        }
+    }
+    Ok(is_empty)
+}
+
+/// Remove the `.crate` files and cached index entries from the registry of the
+/// cargo home that belong to none of the lockfiles, e.g. crates superseded by
+/// an update or index entries cargo only looked at while resolving.
+pub fn prune_registry(cargo_home: &Path, lockfiles: &[PathBuf]) -> io::Result<PruneSummary> {
+    let mut crate_files = BTreeSet::new();
+    let mut index_names = BTreeSet::new();
+    let mut git_revisions = BTreeSet::new();
+    for lockfile in lockfiles.iter().filter(|lockfile| lockfile.is_file()) {
+        for package in load_lockfile(lockfile)?.packages {
+            let Some(source) = &package.source else {
+                continue;
+            };
+            if source.is_registry() {
+                crate_files.insert(format!("{}-{}.crate", package.name, package.version));
+                index_names.insert(package.name.to_string().to_lowercase());
+            } else if let Some(revision) = source.precise().filter(|_| source.is_git()) {
+                git_revisions.insert(revision.to_string());
+            }
+        }
+    }
+
+    let mut summary = PruneSummary::default();
+    let registry_cache = cargo_home.join("registry").join("cache");
+    if registry_cache.is_dir() {
+        for registry_dir in fs::read_dir(&registry_cache)? {
+            for entry in fs::read_dir(registry_dir?.path())? {
+                let entry = entry?;
+                if !crate_files.contains(&entry.file_name().to_string_lossy().to_string()) {
+                    remove_file(&entry.path(), &mut summary)?;
+                    summary.crates.push(entry.path());
+                }
+            }
+        }
+    }
+    let registry_index = cargo_home.join("registry").join("index");
+    if registry_index.is_dir() {
+        for registry_dir in fs::read_dir(&registry_index)? {
+            let index_cache = registry_dir?.path().join(".cache");
+            if index_cache.is_dir() {
+                prune_index_cache(&index_cache, &index_names, &mut summary)?;
+            }
+        }
+    }
+
+    let git_dir = cargo_home.join("git");
+    let git_db = git_dir.join("db");
+    if git_db.is_dir() {
+        let size_before = dir_size(&git_dir)?;
+        for db in fs::read_dir(&git_db)? {
+            shrink_git_db(&db?.path(), &git_revisions, &mut summary)?;
+        }
+        // NOTE: Checkouts borrow the objects of the database through an
+        // absolute path, which breaks once the tarball is extracted. Cargo
+        // checks them out again from the database, also offline.
+        let checkouts = git_dir.join("checkouts");
+        if checkouts.is_dir() {
+            fs::remove_dir_all(checkouts)?;
+        }
+        summary.bytes += size_before.saturating_sub(dir_size(&git_dir)?);
+    }
+
+    for crate_file in &summary.crates {
+        info!(
+            ?crate_file,
+            "✂️ Removed crate that is in none of the lockfiles."
+        );
+    }
+    for git_database in &summary.git_databases {
+        info!(
+            ?git_database,
+            "✂️ Removed git database without revisions of the lockfiles."
+        );
+    }
+    debug!(index_entries = ?summary.index_entries);
+    info!(
+        "✂️ Pruned {} crates and {} index entries and reduced git dependencies to {} revisions, saving {}.",
+        summary.crates.len(),
+        summary.index_entries.len(),
+        summary.git_revisions,
+        format_size(summary.bytes)
+    );
+    Ok(summary)
+}
+
+/// Check with `cargo metadata --offline` that cargo finds every dependency of
+/// the lockfiles in the produced tarball. The tarball is extracted over a copy
+/// of the sources, the way it is used in a build, so the check neither relies
+/// on nor changes the cargo home it was made from.
+pub fn verify_offline(archive: &Path, setup_workdir: &Path) -> io::Result<()> {
+    info!(
+        ?archive,
+        "🔍 Checking that the registry tarball works offline."
+    );
+    let extracted = extract_to_tempdir(archive)?;
+    let workdir = tempfile::Builder::new()
+        .prefix(".verify-offline")
+        .rand_bytes(12)
+        .tempdir()?;
+    utils::copy_dir_all(setup_workdir, workdir.path())?;
+    utils::copy_dir_all(extracted.path(), workdir.path())?;
+    let mut lockfiles = Vec::new();
+    find_lockfiles(workdir.path(), &mut lockfiles)?;
+    let previous_cargo_home = std::env::var_os("CARGO_HOME");
+    unsafe {
+        std::env::set_var("CARGO_HOME", workdir.path().join(".cargo"));
+    }
+    let res = lockfiles.iter().try_for_each(|lockfile| {
+        let manifest = lockfile.with_file_name("Cargo.toml");
+        if !manifest.is_file() {
+            debug!(?lockfile, "No manifest in the sources for this lockfile.");
+            return Ok(());
+        }
+        cargo_metadata_offline(&manifest)
+            .map(drop)
+            .inspect_err(|err| {
+                error!(
+                    ?err,
+                    ?lockfile,
+                    "🛑 The registry tarball is missing dependencies of the lockfile."
+                );
+            })
+    });
+    unsafe {
+        match previous_cargo_home {
+            Some(cargo_home) => std::env::set_var("CARGO_HOME", cargo_home),
+            None => std::env::remove_var("CARGO_HOME"),
+        }
+    }
+    res?;
+    info!("🔍 The registry tarball works offline.");
+    Ok(())
+}
@@ -3,15 +3,25 @@ use std::io;
 use std::path::Path;
 use std::path::PathBuf;
 
-use libroast::common::Compression;
 use libroast::operations::cli::RoastArgs;
 use libroast::operations::roast::roast_opts;
 #[allow(unused_imports)]
 use tracing::{Level, debug, error, info, trace, warn};
 
+use crate::alternate_registries::configure_alternate_registries;
+use crate::archive_manifest::ArchiveManifest;
+use crate::archive_manifest::keep_unchanged_archive;
 use crate::audit;
 use crate::cargo_commands::*;
 use crate::cli::Opts;
+use crate::cli::RegistryLayout;
+use crate::dependency_changes::report_dependency_changes;
+use crate::local_registry::create_local_registry;
+use crate::merge::merge_into_existing_archive;
+use crate::offline::configure_local_source;
+use crate::policy::perform_policy_checks;
+use crate::prune::prune_registry;
+use crate::prune::verify_offline;
 use crate::toml_manifest::has_dependencies;
 use crate::toml_manifest::is_workspace;
 use crate::toml_manifest::workspace_has_dependencies;
@@ -43,6 +53,8 @@ pub fn run_cargo_vendor_home_registry(
     unsafe {
         std::env::set_var("CARGO_HOME", &home_registry_dot_cargo);
     }
+    configure_local_source(&home_registry_dot_cargo, custom_root, registry)?;
+    configure_alternate_registries(&home_registry_dot_cargo, registry)?;
 
     let res = {
         debug!(?home_registry_dot_cargo);
@@ -102,6 +114,7 @@ pub fn run_cargo_vendor_home_registry(
                     &possible_root_manifest_parent,
                     &possible_root_manifest.to_string_lossy(),
                     registry.respect_lockfile,
+                    registry.offline,
                 )?;
 
                 info!(?setup_workdir, "🌳 Finished setting up workdir.");
@@ -111,12 +124,13 @@ pub fn run_cargo_vendor_home_registry(
                     &possible_root_manifest_parent,
                     &possible_root_manifest.to_string_lossy(),
                     registry.respect_lockfile,
+                    registry.crate_cache.as_deref(),
+                    registry.offline,
                 )?;
 
                 if possible_lockfile.is_file() {
                     let lockfile_bytes = fs::read(&possible_lockfile)?;
-                    hash.update(&lockfile_bytes);
-                    let output_hash = hash.finalize();
+                    let output_hash = blake3::hash(&lockfile_bytes);
                     info!(?output_hash, "🔒 Lockfile hash after: ");
                 }
 
@@ -190,6 +204,7 @@ pub fn run_cargo_vendor_home_registry(
                     full_manifest_path_parent,
                     &full_manifest_path.to_string_lossy(),
                     registry.respect_lockfile,
+                    registry.offline,
                 )?;
 
                 if registry.update {
@@ -214,6 +229,8 @@ pub fn run_cargo_vendor_home_registry(
                     full_manifest_path_parent,
                     &full_manifest_path.to_string_lossy(),
                     registry.respect_lockfile,
+                    registry.crate_cache.as_deref(),
+                    registry.offline,
                 )?;
 
                 info!(
@@ -230,8 +247,7 @@ pub fn run_cargo_vendor_home_registry(
 
             if possible_lockfile.is_file() {
                 let lockfile_bytes = fs::read(&possible_lockfile)?;
-                hash.update(&lockfile_bytes);
-                let output_hash = hash.finalize();
+                let output_hash = blake3::hash(&lockfile_bytes);
                 info!(?output_hash, "🔒 Lockfile hash after: ");
                 info!(
                     ?possible_lockfile,
@@ -282,13 +298,33 @@ pub fn run_cargo_vendor_home_registry(
         if let Ok(audit_result) =
             audit::perform_cargo_audit(&lockfiles, &registry.i_accept_the_risk)
         {
-            audit::process_reports(audit_result).map_err(|err| {
+            audit::process_reports(audit_result).inspect_err(|err| {
                 error!(?err);
-                io::Error::new(io::ErrorKind::Interrupted, err.to_string())
             })?;
         }
 
         info!("🛡️🙂 All lockfiles are audited");
+
+        perform_policy_checks(setup_workdir, custom_root, &lockfiles, registry)?;
+
+        report_dependency_changes(&lockfiles, registry)?;
+
+        let archive_manifest = ArchiveManifest::new(setup_workdir, &lockfiles, registry)?;
+        if keep_unchanged_archive(&archive_manifest, registry)? {
+            info!("📦 Cargo Vendor Home Registry finished.");
+            return Ok(());
+        }
+        archive_manifest.write(home_registry)?;
+
+        match registry.registry_layout {
+            RegistryLayout::CargoHome => {
+                prune_registry(&home_registry_dot_cargo, &lockfiles)?;
+            }
+            RegistryLayout::LocalRegistry => {
+                create_local_registry(&home_registry_dot_cargo, &lockfiles)?;
+            }
+        }
+
         info!("👉🏻🗑️ Removing unneeded directories");
 
         if !global_has_deps {
@@ -326,24 +362,17 @@ pub fn run_cargo_vendor_home_registry(
             }
         }
 
-        let outfile = match &registry.tag {
-            Some(v) => format!("registry-{v}"),
-            None => "registry".to_string(),
-        };
+        merge_into_existing_archive(home_registry, registry)?;
 
-        let mut outfile = PathBuf::from(outfile);
-
-        let extension = match &registry.compression {
-            Compression::Gz => "tar.gz",
-            Compression::Xz => "tar.xz",
-            Compression::Zst | Compression::Zstd => "tar.zst",
-            Compression::Bz2 => "tar.bz",
-            Compression::Not => "tar",
-        };
-
-        if !outfile.set_extension(extension) {
-            return Err(io::Error::other("Unable to set extension"));
-        }
+        let outfile = registry.archive_name(&registry.compression)?;
+        // NOTE: The tarball is only moved into `outdir` once it is verified,
+        // so a broken one never replaces the existing tarball.
+        fs::create_dir_all(&registry.outdir)?;
+        let tempdir_for_archive = tempfile::Builder::new()
+            .prefix(".registry-archive")
+            .rand_bytes(12)
+            .tempdir_in(&registry.outdir)?;
+        let archive = tempdir_for_archive.path().join(&outfile);
 
         let roast_args = RoastArgs {
             silent: false,
@@ -351,8 +380,8 @@ pub fn run_cargo_vendor_home_registry(
             include: None,
             exclude: None,
             additional_paths: None,
-            outfile: Some(outfile),
-            outdir: Some(registry.outdir.to_path_buf()),
+            outfile: Some(outfile.clone()),
+            outdir: Some(tempdir_for_archive.path().to_path_buf()),
             preserve_root: false,
             reproducible: true,
             ignore_git: false,
@@ -360,7 +389,14 @@ pub fn run_cargo_vendor_home_registry(
             subcommands: None,
         };
 
-        roast_opts(&roast_args, false)
+        roast_opts(&roast_args, false)?;
+
+        // Every cargo home tarball is pruned, either above or when merging.
+        if registry.registry_layout == RegistryLayout::CargoHome {
+            verify_offline(&archive, setup_workdir)?;
+        }
+        fs::rename(&archive, registry.outdir.join(&outfile))?;
+        Ok(())
     };
 
     res.inspect(|val| {
@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap::ArgAction;
+use clap::CommandFactory;
+use quick_xml::escape::escape;
+use serde::Deserialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::Opts;
+use crate::cli::ServiceDefinitionArgs;
+use crate::service_file::SERVICE_NAME;
+
+/// Where OBS looks for the definitions of the installed services.
+pub const INSTALLED_SERVICE_DEFINITION: &str = "/usr/lib/obs/service/cargo_vendor.service";
+
+const DESCRIPTION: &str = "This service extracts a Rust application source,
+  searches for a Rust application containing a Cargo.toml file,
+  download all crates.io and dependecies,
+  and creates a vendor.tar[.<tar compression>] to be committed allowing fully offline
+  builds of Rust applications.";
+
+/// Arguments that make no sense as a parameter of a `_service` file.
+const NOT_PARAMETERS: &[&str] = &["help", "version", "color", "from_service"];
+
+/// A `<parameter>` of the service definition.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ParameterDefinition {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(rename = "allowedvalues", default)]
+    pub allowed_values: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ServiceDefinition {
+    #[serde(rename = "parameter", default)]
+    parameters: Vec<ParameterDefinition>,
+}
+
+/// The parameters of the service, from the command line options.
+pub fn parameter_definitions() -> Vec<ParameterDefinition> {
+    let mut command = Opts::command();
+    command.build();
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !NOT_PARAMETERS.contains(&arg.get_id().as_str()))
+        .filter_map(|arg| {
+            let name = arg.get_long()?.to_string();
+            let mut description = arg
+                .get_help()
+                .map(|help| help.to_string())
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !description.is_empty() && !description.ends_with('.') {
+                description.push('.');
+            }
+            let defaults: Vec<String> = arg
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_string_lossy().to_string())
+                .filter(|value| !value.is_empty())
+                .collect();
+            if !defaults.is_empty() && !arg.is_hide_default_value_set() {
+                let _ = write!(description, " Default: {}.", defaults.join(", "));
+            }
+            if let Some(aliases) = arg.get_visible_aliases() {
+                let _ = write!(description, " Aliases: {}.", aliases.join(", "));
+            }
+            if matches!(arg.get_action(), ArgAction::Append) {
+                description.push_str(" Can be set more than once.");
+            }
+            let allowed_values = arg
+                .get_possible_values()
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_string())
+                .collect();
+            Some(ParameterDefinition {
+                name,
+                description: description.trim_start().to_string(),
+                allowed_values,
+            })
+        })
+        .collect()
+}
+
+/// The `cargo_vendor.service` file OBS reads the parameters of the service
+/// from.
+pub fn service_definition() -> String {
+    let command = Opts::command();
+    let summary = command
+        .get_about()
+        .map(|about| about.to_string())
+        .unwrap_or_default();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(xml, "<service name=\"{SERVICE_NAME}\">");
+    let _ = writeln!(xml, "   <summary>{}</summary>", escape(summary.as_str()));
+    let _ = writeln!(
+        xml,
+        "   <description><![CDATA[{DESCRIPTION}]]></description>"
+    );
+    for parameter in parameter_definitions() {
+        let _ = writeln!(xml, "   <parameter name=\"{}\">", parameter.name);
+        let _ = writeln!(
+            xml,
+            "      <description>{}</description>",
+            escape(parameter.description.as_str())
+        );
+        for value in &parameter.allowed_values {
+            let _ = writeln!(
+                xml,
+                "      <allowedvalues>{}</allowedvalues>",
+                escape(value)
+            );
+        }
+        xml.push_str("   </parameter>\n");
+    }
+    xml.push_str("</service>\n");
+    xml
+}
+
+fn normalized(parameter: &ParameterDefinition) -> (String, Vec<String>) {
+    let description = parameter
+        .description
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut allowed_values = parameter.allowed_values.clone();
+    allowed_values.sort();
+    (description, allowed_values)
+}
+
+/// Compare a service definition file with the parameters of this binary.
+/// Whitespace in descriptions and the order of allowed values don't matter.
+pub fn check_service_definition(path: &Path) -> io::Result<()> {
+    let content = fs::read_to_string(path).inspect_err(|err| {
+        error!(?err, ?path, "Failed to read the service definition.");
+    })?;
+    let installed: ServiceDefinition = quick_xml::de::from_str(&content).map_err(|err| {
+        error!(?err, ?path, "Failed to parse the service definition.");
+        io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+    })?;
+    let installed: BTreeMap<String, ParameterDefinition> = installed
+        .parameters
+        .into_iter()
+        .map(|parameter| (parameter.name.clone(), parameter))
+        .collect();
+    let expected: BTreeMap<String, ParameterDefinition> = parameter_definitions()
+        .into_iter()
+        .map(|parameter| (parameter.name.clone(), parameter))
+        .collect();
+
+    let mut differences = Vec::new();
+    for (name, parameter) in &expected {
+        match installed.get(name) {
+            None => differences.push(format!("`{name}` is missing.")),
+            Some(installed) if normalized(installed) != normalized(parameter) => {
+                differences.push(format!(
+                    "`{name}` differs. Expected description `{}` and allowed values {:?}.",
+                    parameter.description, parameter.allowed_values
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+    for name in installed
+        .keys()
+        .filter(|name| !expected.contains_key(*name))
+    {
+        differences.push(format!("`{name}` is not a parameter of this version."));
+    }
+    if differences.is_empty() {
+        info!(?path, "✅ The service definition matches this version.");
+        return Ok(());
+    }
+    for difference in &differences {
+        error!("🛑 {}", difference);
+    }
+    let msg = format!(
+        "The service definition {} disagrees with this version in {} parameters.",
+        path.display(),
+        differences.len()
+    );
+    error!(msg);
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Print or write the service definition, or check an existing one.
+pub fn run_service_definition(args: &ServiceDefinitionArgs) -> io::Result<()> {
+    if let Some(path) = &args.check {
+        return check_service_definition(path);
+    }
+    let xml = service_definition();
+    match &args.output {
+        Some(path) => {
+            fs::write(path, xml)?;
+            info!(?path, "📝 Wrote the service definition.");
+        }
+        None => print!("{xml}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,238 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::utils;
+use serde::Deserialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::Opts;
+use crate::config_file::parse_with_config_file;
+use crate::dependency_changes::extract_to_tempdir;
+
+/// Name of this service in `_service` files.
+pub const SERVICE_NAME: &str = "cargo_vendor";
+
+/// The `<services>` of a `_service` file.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ServiceFile {
+    #[serde(rename = "service", default)]
+    pub services: Vec<Service>,
+}
+
+/// A `<service>` block of a `_service` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Service {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@mode", default)]
+    pub mode: Option<String>,
+    #[serde(rename = "param", default)]
+    pub params: Vec<ServiceParam>,
+}
+
+impl Service {
+    /// Whether `osc` runs the service on the packager's machine. `buildtime`
+    /// services only run in the build and `serveronly` ones on the server.
+    pub fn runs_locally(&self) -> bool {
+        !matches!(self.mode.as_deref(), Some("buildtime" | "serveronly"))
+    }
+}
+
+/// A `<param>` of a service block.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceParam {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "$text", default)]
+    pub value: String,
+}
+
+impl ServiceFile {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        quick_xml::de::from_str(&content).map_err(|err| {
+            error!(?err, ?path, "Failed to parse the service file.");
+            io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+        })
+    }
+}
+
+impl Service {
+    /// The command line `osc` runs the service with: every parameter becomes
+    /// `--name value` and `--outdir` is appended.
+    pub fn arguments(&self, outdir: &Path) -> Vec<String> {
+        let mut arguments = vec![SERVICE_NAME.to_string()];
+        for param in &self.params {
+            arguments.push(format!("--{}", param.name));
+            arguments.push(param.value.trim().to_string());
+        }
+        arguments.push("--outdir".to_string());
+        arguments.push(outdir.to_string_lossy().to_string());
+        arguments
+    }
+}
+
+/// Outcome of one `cargo_vendor` service of the service file.
+#[derive(Debug)]
+struct ServiceRun {
+    /// Position of the block in the service file, starting at 1.
+    position: usize,
+    archive: Option<PathBuf>,
+    result: io::Result<()>,
+}
+
+/// Source tarballs extracted for the services, by path.
+pub(crate) type SharedSources = BTreeMap<PathBuf, tempfile::TempDir>;
+
+/// Extract a source tarball once for all services using it. Directories and
+/// URLs are left to each service.
+pub(crate) fn shared_source(
+    opts: &Opts,
+    sources: &mut SharedSources,
+) -> io::Result<Option<PathBuf>> {
+    if url::Url::parse(&opts.src).is_ok() {
+        return Ok(None);
+    }
+    let Ok(target) = utils::process_globs(Path::new(&opts.src)) else {
+        return Ok(None);
+    };
+    if !target.is_file() || utils::is_supported_format(&target).is_err() {
+        return Ok(None);
+    }
+    if let Some(extracted) = sources.get(&target) {
+        info!(?target, "♻️ Reusing the extracted source.");
+        return Ok(Some(extracted.path().to_path_buf()));
+    }
+    let extracted = extract_to_tempdir(&target)?;
+    let path = extracted.path().to_path_buf();
+    sources.insert(target, extracted);
+    Ok(Some(path))
+}
+
+/// The options of a `cargo_vendor` service. The configuration file and
+/// relative paths are resolved against `package_dir`, the directory of the
+/// `_service` file.
+pub(crate) fn service_opts(
+    service: &Service,
+    outdir: &Path,
+    package_dir: &Path,
+) -> io::Result<Opts> {
+    if service
+        .params
+        .iter()
+        .any(|param| param.name == "from-service")
+    {
+        let msg = "A service of the service file can't run another service file.";
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    let arguments = service.arguments(outdir);
+    info!("🧾 Running {}", arguments.join(" "));
+    parse_with_config_file(&arguments, package_dir)
+}
+
+fn run_service(
+    service: &Service,
+    outdir: &Path,
+    package_dir: &Path,
+    sources: &mut SharedSources,
+) -> (Option<PathBuf>, io::Result<()>) {
+    let mut opts = match service_opts(service, outdir, package_dir) {
+        Ok(opts) => opts,
+        Err(err) => return (None, Err(err)),
+    };
+    let archive = opts.archive_name(&opts.compression).ok();
+    let result = shared_source(&opts, sources).and_then(|extracted| {
+        opts.extracted_src = extracted;
+        opts.run_vendor()
+    });
+    (archive, result)
+}
+
+/// Run every `cargo_vendor` service of the service file in order, as `osc`
+/// would, and log a summary of all of them at the end. The remaining services
+/// still run if one fails.
+pub fn run_service_file(service_file: &Path, opts: &Opts) -> io::Result<()> {
+    let services = ServiceFile::read(service_file)?.services;
+    info!(
+        ?service_file,
+        "🗂️ Running the {} services of the service file.", SERVICE_NAME
+    );
+    let package_dir = match service_file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut sources = SharedSources::new();
+    let mut runs = Vec::new();
+    for (index, service) in services.iter().enumerate() {
+        let position = index + 1;
+        if service.name != SERVICE_NAME {
+            info!("⏭️ Skipping service {} `{}`.", position, service.name);
+            continue;
+        }
+        if !service.runs_locally() {
+            info!(
+                "⏭️ Skipping service {} with mode `{}`.",
+                position,
+                service.mode.as_deref().unwrap_or_default()
+            );
+            continue;
+        }
+        let (archive, result) = run_service(service, &opts.outdir, package_dir, &mut sources);
+        runs.push(ServiceRun {
+            position,
+            archive,
+            result,
+        });
+    }
+    if runs.is_empty() {
+        let msg = format!(
+            "No {} service found in {}.",
+            SERVICE_NAME,
+            service_file.display()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+    }
+
+    info!("🗂️ Summary of {}:", service_file.display());
+    let mut failed = 0;
+    for run in &runs {
+        let archive = run
+            .archive
+            .as_ref()
+            .map(|archive| archive.display().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        match &run.result {
+            Ok(()) => info!("✅ Service {}: {}", run.position, archive),
+            Err(err) => {
+                failed += 1;
+                error!("🛑 Service {}: {}: {}", run.position, archive, err);
+            }
+        }
+    }
+    if failed > 0 {
+        let msg = format!(
+            "{} of {} {} services failed.",
+            failed,
+            runs.len(),
+            SERVICE_NAME
+        );
+        error!(msg);
+        return Err(io::Error::other(msg));
+    }
+    info!("🗂️ All {} {} services succeeded.", runs.len(), SERVICE_NAME);
+    Ok(())
+}
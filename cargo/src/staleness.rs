@@ -0,0 +1,241 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libroast::utils;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::alternate_registries::configure_alternate_registries;
+use crate::archive_manifest::ArchiveManifest;
+use crate::audit::load_lockfile;
+use crate::cargo_commands::cargo_metadata_offline;
+use crate::cli::Method;
+use crate::cli::Opts;
+use crate::dependency_changes::archive_lockfiles;
+
+/// A locked version of a crate, with the revision if it comes from git.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LockedVersion {
+    version: semver::Version,
+    revision: Option<String>,
+}
+
+impl std::fmt::Display for LockedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.revision {
+            Some(revision) => write!(f, "{}#{}", self.version, revision),
+            None => write!(f, "{}", self.version),
+        }
+    }
+}
+
+/// Locked versions of the crates of a lockfile that come from a registry or
+/// git, by name and source. The revision of git sources is kept apart from
+/// the source.
+fn locked_versions(lockfile: &Path) -> io::Result<BTreeMap<(String, String), Vec<LockedVersion>>> {
+    let mut versions: BTreeMap<(String, String), Vec<LockedVersion>> = BTreeMap::new();
+    for package in load_lockfile(lockfile)?.packages {
+        let Some(source) = &package.source else {
+            continue;
+        };
+        let source = source.to_string();
+        let (source, revision) = match source.split_once('#') {
+            Some((url, revision)) => (url, Some(revision.to_string())),
+            None => (source.as_str(), None),
+        };
+        versions
+            .entry((package.name.to_string(), source.to_string()))
+            .or_default()
+            .push(LockedVersion {
+                version: package.version.clone(),
+                revision,
+            });
+    }
+    Ok(versions)
+}
+
+fn is_compatible(locked: &semver::Version, required: &semver::Version) -> bool {
+    locked.major == required.major && (required.major != 0 || locked.minor == required.minor)
+}
+
+/// Crates the lockfile of the sources locks at a version the lockfile of the
+/// tarball does not satisfy. If `exact`, the versions and git revisions have
+/// to be the same. Otherwise the tarball may have newer compatible versions at
+/// any revision since vendoring updates.
+fn compare_lockfiles(
+    relative: &Path,
+    source_lockfile: &Path,
+    archive_lockfile: &Path,
+    exact: bool,
+) -> io::Result<Vec<String>> {
+    let source = locked_versions(source_lockfile)?;
+    let archive = locked_versions(archive_lockfile)?;
+    let mut differences = Vec::new();
+    for ((name, origin), versions) in &source {
+        let archived = archive
+            .get(&(name.clone(), origin.clone()))
+            .cloned()
+            .unwrap_or_default();
+        for locked in versions {
+            let satisfied = archived.iter().any(|archived| {
+                if exact {
+                    archived == locked
+                } else {
+                    archived.version >= locked.version
+                        && is_compatible(&archived.version, &locked.version)
+                }
+            });
+            if !satisfied {
+                let archived = archived.iter().map(ToString::to_string).collect::<Vec<_>>();
+                differences.push(format!(
+                    "{}: {} {} from {} is locked by the sources, but the tarball has {}.",
+                    relative.display(),
+                    name,
+                    locked,
+                    origin,
+                    if archived.is_empty() {
+                        "none".to_string()
+                    } else {
+                        archived.join(", ")
+                    }
+                ));
+            }
+        }
+    }
+    Ok(differences)
+}
+
+/// The tarball of these options, looked up in `outdir` and then in the
+/// current directory.
+fn find_archive(opts: &Opts) -> io::Result<PathBuf> {
+    let archive_name = opts.archive_name(&opts.compression)?;
+    [opts.outdir.join(&archive_name), archive_name.clone()]
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            let msg = format!(
+                "No tarball {} to check in outdir or the current directory. Run cargo_vendor to create it.",
+                archive_name.display()
+            );
+            error!(msg);
+            io::Error::new(io::ErrorKind::NotFound, msg)
+        })
+}
+
+/// First error line of cargo, e.g. that the lockfile needs to be updated.
+fn cargo_error(err: &io::Error) -> String {
+    let message = err.to_string();
+    message
+        .lines()
+        .find(|line| line.trim_start().starts_with("error"))
+        .unwrap_or(message.lines().next().unwrap_or_default())
+        .trim()
+        .to_string()
+}
+
+/// Check without the network whether the existing tarball still fits the
+/// sources: the lockfiles of the sources have to be satisfied by the ones of
+/// the tarball, and cargo has to resolve every manifest with only the
+/// lockfiles and crates of the tarball.
+pub fn check_staleness(setup_workdir: &Path, custom_root: &Path, opts: &Opts) -> io::Result<()> {
+    let archive = find_archive(opts)?;
+    info!(?archive, "🕰️ Checking whether the tarball is stale.");
+    let (extracted, _) = archive_lockfiles(&archive)?;
+    if let Some(manifest) = ArchiveManifest::read(extracted.path())? {
+        info!(
+            "🕰️ The tarball was vendored from {} by obs-service-cargo {}.",
+            manifest.source.name, manifest.obs_service_cargo_version
+        );
+    }
+    let exact = !opts.update && opts.update_crate.is_empty();
+    let manifests: Vec<PathBuf> = opts
+        .manifests(custom_root)
+        .into_iter()
+        .filter(|manifest| manifest.is_file())
+        .collect();
+
+    let mut differences = Vec::new();
+    for manifest in &manifests {
+        let lockfile = manifest.with_file_name("Cargo.lock");
+        if !lockfile.is_file() {
+            continue;
+        }
+        let relative = lockfile.strip_prefix(setup_workdir).unwrap_or(&lockfile);
+        let archive_lockfile = extracted.path().join(relative);
+        if !archive_lockfile.is_file() {
+            differences.push(format!(
+                "{} is in the sources but not in the tarball.",
+                relative.display()
+            ));
+            continue;
+        }
+        differences.extend(compare_lockfiles(
+            relative,
+            &lockfile,
+            &archive_lockfile,
+            exact,
+        )?);
+    }
+
+    // NOTE: Like the build, use the sources with the tarball extracted on
+    // top of them. Both are copied so the sources are left untouched.
+    let tempdir_for_workdir = tempfile::Builder::new()
+        .prefix(".check-staleness")
+        .rand_bytes(12)
+        .tempdir()?;
+    let workdir = tempdir_for_workdir.path();
+    utils::copy_dir_all(setup_workdir, workdir)?;
+    utils::copy_dir_all(extracted.path(), workdir)?;
+    let tempdir_for_home = tempfile::Builder::new()
+        .prefix(".cargo")
+        .rand_bytes(12)
+        .tempdir()?;
+    let cargo_home = match opts.method {
+        Method::Registry => workdir.join(".cargo"),
+        Method::Vendor => tempdir_for_home.path().to_path_buf(),
+    };
+    unsafe {
+        std::env::set_var("CARGO_HOME", &cargo_home);
+    }
+    // NOTE: Define the alternate registries the way vendoring does.
+    configure_alternate_registries(&cargo_home, opts)?;
+    for manifest in &manifests {
+        let Ok(relative) = manifest.strip_prefix(setup_workdir) else {
+            debug!(?manifest, "Skipping manifest outside of the sources.");
+            continue;
+        };
+        debug!(?relative, "Resolving offline.");
+        if let Err(err) = cargo_metadata_offline(&workdir.join(relative)) {
+            differences.push(format!(
+                "{} can't be resolved with the tarball: {}",
+                relative.display(),
+                cargo_error(&err)
+            ));
+        }
+    }
+
+    if differences.is_empty() {
+        info!(?archive, "✅ The tarball is up to date with the sources.");
+        return Ok(());
+    }
+    for difference in &differences {
+        error!("🛑 {}", difference);
+    }
+    let msg = format!(
+        "{} is stale: found {} differences with the sources. Re-run cargo_vendor to update it.",
+        archive.display(),
+        differences.len()
+    );
+    error!(msg);
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
@@ -6,6 +6,9 @@ use std::fs;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+use toml::Spanned;
+use toml::de::DeTable;
+use toml::de::DeValue;
 
 #[allow(unused_imports)]
 use tracing::{Level, debug, error, info, trace, warn};
@@ -189,3 +192,132 @@ pub fn has_dependencies(src: &Path) -> io::Result<bool> {
         src.to_string_lossy()
     )))
 }
+
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// A detailed dependency specification found in a manifest, either in a
+/// dependency table or in a `[patch]` or `[replace]` entry. Only string
+/// fields are kept, each with the line it was found on.
+#[derive(Debug, Clone)]
+pub struct DependencyReference {
+    pub manifest: PathBuf,
+    pub table: String,
+    pub name: String,
+    pub fields: BTreeMap<String, (String, usize)>,
+}
+
+impl DependencyReference {
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(|(value, _)| value.as_str())
+    }
+
+    pub fn line_of(&self, key: &str) -> Option<usize> {
+        self.fields.get(key).map(|(_, line)| *line)
+    }
+}
+
+pub(crate) fn line_of(text: &str, offset: usize) -> usize {
+    text.get(..offset)
+        .map(|head| head.matches('\n').count() + 1)
+        .unwrap_or(1)
+}
+
+pub(crate) fn get<'a, 'i>(table: &'a DeTable<'i>, key: &str) -> Option<&'a Spanned<DeValue<'i>>> {
+    table
+        .iter()
+        .find(|(k, _)| k.get_ref().as_ref() == key)
+        .map(|(_, v)| v)
+}
+
+pub(crate) fn get_table<'a, 'i>(table: &'a DeTable<'i>, key: &str) -> Option<&'a DeTable<'i>> {
+    get(table, key).and_then(|value| value.get_ref().as_table())
+}
+
+fn collect_from_dependency_table(
+    text: &str,
+    manifest: &Path,
+    table_name: &str,
+    table: &DeTable<'_>,
+    references: &mut Vec<DependencyReference>,
+) {
+    for (name, value) in table.iter() {
+        let Some(spec) = value.get_ref().as_table() else {
+            continue;
+        };
+        let fields = spec
+            .iter()
+            .filter_map(|(key, value)| {
+                let string = value.get_ref().as_str()?;
+                Some((
+                    key.get_ref().to_string(),
+                    (string.to_string(), line_of(text, value.span().start)),
+                ))
+            })
+            .collect();
+        references.push(DependencyReference {
+            manifest: manifest.to_path_buf(),
+            table: table_name.to_string(),
+            name: name.get_ref().to_string(),
+            fields,
+        });
+    }
+}
+
+/// Collect every detailed dependency specification of a manifest. These are
+/// found in the dependency tables including platform specific ones,
+/// `[workspace.dependencies]`, `[patch]` and `[replace]`.
+pub fn dependency_references(manifest: &Path) -> io::Result<Vec<DependencyReference>> {
+    let text = fs::read_to_string(manifest)?;
+    let document = DeTable::parse(&text).map_err(|err| {
+        error!(?err, ?manifest, "Failed to parse manifest.");
+        io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+    })?;
+    let document = document.get_ref();
+    let mut references = Vec::new();
+
+    let mut scan = |table_name: String, table: &DeTable<'_>| {
+        collect_from_dependency_table(&text, manifest, &table_name, table, &mut references);
+    };
+
+    for dependency_table in DEPENDENCY_TABLES {
+        if let Some(table) = get_table(document, dependency_table) {
+            scan(dependency_table.to_string(), table);
+        }
+    }
+
+    if let Some(targets) = get_table(document, "target") {
+        for (platform, target) in targets.iter() {
+            let Some(target) = target.get_ref().as_table() else {
+                continue;
+            };
+            for dependency_table in DEPENDENCY_TABLES {
+                if let Some(table) = get_table(target, dependency_table) {
+                    scan(
+                        format!("target.'{}'.{}", platform.get_ref(), dependency_table),
+                        table,
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(table) = get_table(document, "workspace").and_then(|w| get_table(w, "dependencies"))
+    {
+        scan("workspace.dependencies".to_string(), table);
+    }
+
+    if let Some(patches) = get_table(document, "patch") {
+        for (source, table) in patches.iter() {
+            if let Some(table) = table.get_ref().as_table() {
+                scan(format!("patch.{}", source.get_ref()), table);
+            }
+        }
+    }
+
+    if let Some(table) = get_table(document, "replace") {
+        scan("replace".to_string(), table);
+    }
+
+    debug!(?manifest, ?references, "Collected dependency references");
+    Ok(references)
+}
@@ -0,0 +1,374 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::Opts;
+use crate::policy::CrateSpec;
+use crate::policy::Policy;
+
+/// Where cargo-deny looks for its configuration, relative to a project root.
+pub const DENY_TOML_PATHS: &[&str] = &["deny.toml", ".deny.toml", ".cargo/deny.toml"];
+/// Where cargo-audit looks for its configuration, relative to a project root.
+pub const AUDIT_TOML_PATHS: &[&str] = &[".cargo/audit.toml"];
+
+// NOTE: These only pick the parts we understand. Upstream files are parsed
+// leniently since both tools have changed their layout over time.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DenyToml {
+    #[serde(default)]
+    advisories: AdvisoriesConfig,
+    #[serde(default)]
+    bans: DenyBans,
+    #[serde(default)]
+    sources: DenySources,
+    #[serde(default)]
+    licenses: DenyLicenses,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AuditToml {
+    #[serde(default)]
+    advisories: AdvisoriesConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AdvisoriesConfig {
+    #[serde(default)]
+    ignore: Vec<toml::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DenyBans {
+    multiple_versions: Option<String>,
+    #[serde(default)]
+    deny: Vec<toml::Value>,
+    #[serde(default)]
+    skip: Vec<toml::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DenySources {
+    #[serde(default)]
+    allow_registry: Vec<String>,
+    #[serde(default)]
+    allow_git: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DenyLicenses {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    exceptions: Vec<toml::Value>,
+}
+
+/// An advisory that upstream decided to ignore.
+#[derive(Debug, Clone)]
+pub struct UpstreamIgnore {
+    pub id: String,
+    pub reason: Option<String>,
+    pub origin: PathBuf,
+}
+
+/// Everything found in the `deny.toml` and `.cargo/audit.toml` of the sources.
+#[derive(Debug, Default, Clone)]
+pub struct UpstreamAuditConfig {
+    pub files: Vec<PathBuf>,
+    pub ignores: Vec<UpstreamIgnore>,
+    /// Crates upstream ignores advisories for. These can't be mapped to
+    /// advisory ids and are only shown.
+    pub ignored_crates: Vec<String>,
+    pub policy: Policy,
+    pub multiple_versions: Option<String>,
+    /// Registries and git repositories upstream allows. These are only shown,
+    /// upstream can't widen the allowlists of the packager.
+    pub allow_registry: Vec<String>,
+    pub allow_git: Vec<String>,
+    pub license_allow: Vec<String>,
+    pub license_exceptions: Vec<String>,
+}
+
+fn str_field<'a>(value: &'a toml::Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(toml::Value::as_str)
+}
+
+/// A cargo-deny crate entry, either `name`, `name@version` or a table with
+/// `crate` or `name` and `version`. Other keys of the table are dropped.
+fn crate_spec(value: &toml::Value) -> Option<CrateSpec> {
+    if let Some(spec) = value.as_str() {
+        return spec.parse().ok();
+    }
+    let spec = str_field(value, "crate").or_else(|| str_field(value, "name"))?;
+    let mut spec: CrateSpec = match str_field(value, "version") {
+        Some(version) => format!("{spec}@{version}").parse().ok()?,
+        None => spec.parse().ok()?,
+    };
+    spec.reason = str_field(value, "reason").map(str::to_string);
+    Some(spec)
+}
+
+fn crate_specs(origin: &Path, values: &[toml::Value]) -> Vec<CrateSpec> {
+    values
+        .iter()
+        .filter_map(|value| {
+            let spec = crate_spec(value);
+            if spec.is_none() {
+                warn!(
+                    "⚠️ Skipping unsupported crate entry `{}` in {}.",
+                    value,
+                    origin.display()
+                );
+            }
+            spec
+        })
+        .collect()
+}
+
+/// A cargo-deny license exception as `name: license, license`.
+fn license_exception(value: &toml::Value) -> Option<String> {
+    let name = str_field(value, "crate").or_else(|| str_field(value, "name"))?;
+    let licenses = value
+        .get("allow")
+        .and_then(toml::Value::as_array)
+        .map(|licenses| {
+            licenses
+                .iter()
+                .filter_map(toml::Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+    Some(format!("{name}: {licenses}"))
+}
+
+impl UpstreamAuditConfig {
+    fn add_ignores(&mut self, origin: &Path, values: &[toml::Value]) {
+        for value in values {
+            let id = value.as_str().or_else(|| str_field(value, "id"));
+            let reason = str_field(value, "reason").map(str::to_string);
+            if let Some(id) = id {
+                self.ignores.push(UpstreamIgnore {
+                    id: id.to_string(),
+                    reason,
+                    origin: origin.to_path_buf(),
+                });
+            } else if let Some(spec) = str_field(value, "crate") {
+                self.ignored_crates.push(spec.to_string());
+            }
+        }
+    }
+
+    fn add_deny_toml(&mut self, origin: &Path, deny_toml: DenyToml) {
+        self.add_ignores(origin, &deny_toml.advisories.ignore);
+        self.policy
+            .bans
+            .deny
+            .extend(crate_specs(origin, &deny_toml.bans.deny));
+        self.policy
+            .bans
+            .skip
+            .extend(crate_specs(origin, &deny_toml.bans.skip));
+        if deny_toml.bans.multiple_versions.is_some() {
+            self.multiple_versions = deny_toml.bans.multiple_versions;
+        }
+        self.allow_registry.extend(deny_toml.sources.allow_registry);
+        self.allow_git.extend(deny_toml.sources.allow_git);
+        self.license_allow.extend(deny_toml.licenses.allow);
+        self.license_exceptions.extend(
+            deny_toml
+                .licenses
+                .exceptions
+                .iter()
+                .filter_map(license_exception),
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Show the packager what upstream configured.
+    pub fn show(&self) {
+        for file in &self.files {
+            info!("🔎 Found upstream audit configuration {}", file.display());
+        }
+        if !self.ignores.is_empty() {
+            info!("🔎 Upstream ignores {} advisories:", self.ignores.len());
+            for ignore in &self.ignores {
+                info!(
+                    "- {} ({}): {}",
+                    ignore.id,
+                    ignore.origin.display(),
+                    ignore.reason.as_deref().unwrap_or("no reason given")
+                );
+            }
+        }
+        if !self.ignored_crates.is_empty() {
+            warn!(
+                "⚠️ Upstream ignores advisories of whole crates, which can't be applied: {}",
+                self.ignored_crates.join(", ")
+            );
+        }
+        for spec in &self.policy.bans.deny {
+            info!(
+                "🔎 Upstream denies `{}`{}",
+                spec,
+                spec.reason
+                    .as_ref()
+                    .map(|reason| format!(": {reason}"))
+                    .unwrap_or_default()
+            );
+        }
+        if !self.policy.bans.skip.is_empty() {
+            info!(
+                "🔎 Upstream skips multiple versions of {}",
+                self.policy
+                    .bans
+                    .skip
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if let Some(multiple_versions) = &self.multiple_versions {
+            info!(
+                "🔎 Upstream sets multiple-versions to `{}`",
+                multiple_versions
+            );
+        }
+        if !self.allow_registry.is_empty() || !self.allow_git.is_empty() {
+            info!(
+                "🔎 Upstream allows registries [{}] and git repositories [{}], which are not applied",
+                self.allow_registry.join(", "),
+                self.allow_git.join(", ")
+            );
+        }
+        if !self.license_allow.is_empty() {
+            info!(
+                "🔎 Upstream allows licenses {}",
+                self.license_allow.join(", ")
+            );
+        }
+        for exception in &self.license_exceptions {
+            info!("🔎 Upstream license exception for {}", exception);
+        }
+    }
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(path: &Path) -> io::Result<T> {
+    let content = fs::read_to_string(path)?;
+    toml::from_str(&content).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid upstream audit configuration {}: {}",
+                path.display(),
+                err
+            ),
+        )
+    })
+}
+
+/// Read `deny.toml` and `.cargo/audit.toml` from the custom root and the top
+/// level of the sources. Files that fail to parse are skipped with a warning
+/// unless `strict` is set.
+pub fn load_upstream_audit_config(
+    setup_workdir: &Path,
+    custom_root: &Path,
+    strict: bool,
+) -> io::Result<UpstreamAuditConfig> {
+    let mut config = UpstreamAuditConfig::default();
+    let mut roots = vec![custom_root.to_path_buf()];
+    if setup_workdir.canonicalize().ok() != custom_root.canonicalize().ok() {
+        roots.push(setup_workdir.to_path_buf());
+    }
+    for root in &roots {
+        for (relative, is_deny_toml) in DENY_TOML_PATHS
+            .iter()
+            .map(|path| (path, true))
+            .chain(AUDIT_TOML_PATHS.iter().map(|path| (path, false)))
+        {
+            let path = root.join(relative);
+            if !path.is_file() {
+                continue;
+            }
+            let origin = path
+                .strip_prefix(setup_workdir)
+                .unwrap_or(&path)
+                .to_path_buf();
+            let result = if is_deny_toml {
+                parse::<DenyToml>(&path).map(|deny_toml| config.add_deny_toml(&origin, deny_toml))
+            } else {
+                parse::<AuditToml>(&path)
+                    .map(|audit_toml| config.add_ignores(&origin, &audit_toml.advisories.ignore))
+            };
+            match result {
+                Ok(()) => config.files.push(origin),
+                Err(err) if strict => {
+                    error!(?err);
+                    return Err(err);
+                }
+                Err(err) => warn!("⚠️ {}", err),
+            }
+        }
+    }
+    debug!(?config);
+    Ok(config)
+}
+
+/// Show the upstream audit configuration and, if the packager trusts it,
+/// merge its ignores into `i_accept_the_risk` and its bans and sources into
+/// the policy.
+pub fn apply_upstream_audit_config(
+    setup_workdir: &Path,
+    custom_root: &Path,
+    opts: &mut Opts,
+) -> io::Result<()> {
+    let config =
+        load_upstream_audit_config(setup_workdir, custom_root, opts.trust_upstream_audit_config)?;
+    if config.is_empty() {
+        return Ok(());
+    }
+    config.show();
+    if !opts.trust_upstream_audit_config {
+        info!(
+            "ℹ️ Upstream audit configuration is not applied. Set `trust-upstream-audit-config` to apply it."
+        );
+        return Ok(());
+    }
+    for ignore in &config.ignores {
+        if opts.i_accept_the_risk.contains(&ignore.id) {
+            continue;
+        }
+        warn!(
+            "⚠️ Accepted risk from {} - {}: {}",
+            ignore.origin.display(),
+            ignore.id,
+            ignore.reason.as_deref().unwrap_or("no reason given")
+        );
+        opts.i_accept_the_risk.push(ignore.id.clone());
+    }
+    opts.upstream_policy = Some(config.policy);
+    Ok(())
+}
@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::cli::Method;
+use crate::cli::Opts;
+
+/// Key of the hints under `[package.metadata]` and `[workspace.metadata]`.
+pub const METADATA_KEY: &str = "obs-service-cargo";
+
+const KNOWN_KEYS: &[&str] = &["manifests", "features", "platforms", "dev-dependencies"];
+
+/// Packaging hints upstream declares in the root manifest, e.g.
+///
+/// ```toml
+/// [workspace.metadata.obs-service-cargo]
+/// manifests = ["xtask/Cargo.toml"]
+/// features = ["cli"]
+/// platforms = ["*-unknown-linux-musl"]
+/// dev-dependencies = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpstreamHints {
+    /// Other manifests to vendor, relative to the root manifest.
+    #[serde(default)]
+    pub manifests: Vec<PathBuf>,
+    /// Features to vendor instead of all of them when filtering.
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Platforms to keep besides the default ones when filtering.
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Whether the tests need the dev-dependencies. If `false`, they are
+    /// dropped when filtering.
+    pub dev_dependencies: Option<bool>,
+}
+
+impl UpstreamHints {
+    pub fn is_empty(&self) -> bool {
+        self == &UpstreamHints::default()
+    }
+
+    /// Settings of `other` win over the ones of `self`.
+    fn merge(&mut self, other: UpstreamHints) {
+        if !other.manifests.is_empty() {
+            self.manifests = other.manifests;
+        }
+        if !other.features.is_empty() {
+            self.features = other.features;
+        }
+        if !other.platforms.is_empty() {
+            self.platforms = other.platforms;
+        }
+        if other.dev_dependencies.is_some() {
+            self.dev_dependencies = other.dev_dependencies;
+        }
+    }
+}
+
+fn hints_table<'a>(manifest: &'a toml::Table, table: &str) -> Option<&'a toml::Value> {
+    manifest.get(table)?.get("metadata")?.get(METADATA_KEY)
+}
+
+/// Read the hints of the root manifest. Hints of `[package.metadata]` win
+/// over the ones of `[workspace.metadata]`. Like the upstream audit
+/// configuration, hints that can't be understood are skipped with a warning.
+pub fn load_upstream_hints(custom_root: &Path) -> io::Result<UpstreamHints> {
+    let manifest_path = custom_root.join("Cargo.toml");
+    let mut hints = UpstreamHints::default();
+    if !manifest_path.is_file() {
+        return Ok(hints);
+    }
+    let manifest = match toml::from_str::<toml::Table>(&fs::read_to_string(&manifest_path)?) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            warn!(?err, ?manifest_path, "⚠️ Failed to read upstream hints.");
+            return Ok(hints);
+        }
+    };
+    for table in ["workspace", "package"] {
+        let Some(value) = hints_table(&manifest, table) else {
+            continue;
+        };
+        let origin = format!("{table}.metadata.{METADATA_KEY}");
+        if let Some(keys) = value.as_table() {
+            for key in keys
+                .keys()
+                .filter(|key| !KNOWN_KEYS.contains(&key.as_str()))
+            {
+                warn!(
+                    "⚠️ Ignoring unknown upstream hint `{}` in [{}].",
+                    key, origin
+                );
+            }
+        }
+        match value.clone().try_into::<UpstreamHints>() {
+            Ok(table_hints) => {
+                debug!(?table_hints, origin);
+                hints.merge(table_hints);
+            }
+            Err(err) => warn!(
+                "⚠️ Ignoring invalid upstream hints in [{}]: {}",
+                origin, err
+            ),
+        }
+    }
+    Ok(hints)
+}
+
+/// A manifest hint has to be a `Cargo.toml` inside the sources.
+fn is_valid_manifest(custom_root: &Path, manifest: &Path) -> bool {
+    manifest
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        && manifest
+            .file_name()
+            .is_some_and(|name| name == "Cargo.toml")
+        && custom_root.join(manifest).is_file()
+}
+
+/// Use the upstream hints as defaults for what the packager did not set and
+/// log which of them were applied.
+pub fn apply_upstream_hints(custom_root: &Path, opts: &mut Opts) -> io::Result<()> {
+    let mut hints = load_upstream_hints(custom_root)?;
+    if hints.is_empty() {
+        return Ok(());
+    }
+    info!("🔎 Found upstream packaging hints in the root manifest.");
+
+    let manifests = std::mem::take(&mut hints.manifests);
+    if !manifests.is_empty() {
+        if opts.manifest_path.is_empty() {
+            for manifest in manifests {
+                if is_valid_manifest(custom_root, &manifest) {
+                    info!("🔎 Applied upstream hint: manifest {}", manifest.display());
+                    opts.manifest_path.push(manifest);
+                } else {
+                    warn!(
+                        "⚠️ Skipping upstream hint: manifest {} is not a Cargo.toml in the sources.",
+                        manifest.display()
+                    );
+                }
+            }
+        } else {
+            info!("ℹ️ Upstream hint `manifests` is not applied since `manifest-path` is set.");
+        }
+    }
+
+    let filter = matches!(opts.method, Method::Vendor)
+        && opts
+            .vendor_specific_args
+            .as_ref()
+            .is_some_and(|args| args.filter);
+    if hints.is_empty() {
+        return Ok(());
+    }
+    if !filter {
+        info!(
+            "ℹ️ Upstream hints `features`, `platforms` and `dev-dependencies` only apply to the vendor method with `filter`."
+        );
+        return Ok(());
+    }
+    if !hints.features.is_empty() {
+        info!(
+            "🔎 Applied upstream hint: features {}",
+            hints.features.join(", ")
+        );
+    }
+    if !hints.platforms.is_empty() {
+        info!(
+            "🔎 Applied upstream hint: platforms {}",
+            hints.platforms.join(", ")
+        );
+    }
+    if let Some(dev_dependencies) = hints.dev_dependencies {
+        info!(
+            "🔎 Applied upstream hint: dev-dependencies {}",
+            dev_dependencies
+        );
+    }
+    opts.upstream_hints = Some(hints);
+    Ok(())
+}
@@ -1,4 +1,3 @@
-use libroast::common::Compression;
 use libroast::operations::cli::RoastArgs;
 use libroast::operations::roast::roast_opts;
 use libroast::utils;
@@ -11,8 +10,16 @@ use std::path::PathBuf;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
+use crate::alternate_registries::add_registries_to_vendor_config;
+use crate::alternate_registries::configure_alternate_registries;
+use crate::archive_manifest::ArchiveManifest;
+use crate::archive_manifest::keep_unchanged_archive;
 use crate::cargo_commands::cargo_vendor;
 use crate::cli::Opts;
+use crate::dependency_changes::report_dependency_changes;
+use crate::merge::merge_into_existing_archive;
+use crate::offline::configure_local_source;
+use crate::policy::perform_policy_checks;
 
 pub fn run_cargo_vendor(
     setup_workdir: &Path,
@@ -42,6 +49,9 @@ pub fn run_cargo_vendor(
     unsafe {
         std::env::set_var("CARGO_HOME", &home_registry_dot_cargo);
     }
+    let respect_source_config =
+        configure_local_source(&home_registry_dot_cargo, custom_root, vendor_opts)?;
+    configure_alternate_registries(&home_registry_dot_cargo, vendor_opts)?;
     // Cargo vendor stdouts the configuration for config.toml
     let vendor_specific_args = vendor_opts
         .vendor_specific_args
@@ -57,7 +67,19 @@ pub fn run_cargo_vendor(
             vendor_opts.update,
             &vendor_opts.update_crate,
             vendor_opts.respect_lockfile,
+            vendor_opts.crate_cache.as_deref(),
+            respect_source_config,
+            vendor_opts.upstream_hints.as_ref(),
+            vendor_opts.offline,
         )? {
+            perform_policy_checks(setup_workdir, custom_root, &[&lockfile], vendor_opts)?;
+            report_dependency_changes(&[&lockfile], vendor_opts)?;
+            let archive_manifest = ArchiveManifest::new(setup_workdir, &[&lockfile], vendor_opts)?;
+            if keep_unchanged_archive(&archive_manifest, vendor_opts)? {
+                info!("📦 Cargo Vendor finished.");
+                return Ok(());
+            }
+            archive_manifest.write(to_vendor_cargo_config_dir)?;
             let lockfile_parent = lockfile.parent().unwrap_or(setup_workdir);
             let lockfile_parent_stripped = lockfile_parent
                 .strip_prefix(setup_workdir)
@@ -90,26 +112,14 @@ pub fn run_cargo_vendor(
             // an existing `cargo.toml` but I doubt that's necessary?
             let path_to_dot_cargo_cargo_config =
                 &target_archive_path_for_dot_cargo.join("config.toml");
+            let cargo_config_output =
+                add_registries_to_vendor_config(&cargo_config_output, vendor_opts)?;
             let mut cargo_config_file = fs::File::create(path_to_dot_cargo_cargo_config)?;
             cargo_config_file.write_all(cargo_config_output.as_bytes())?;
             debug!(?cargo_config_file);
         }
-        let outfile = match &vendor_opts.tag {
-            Some(v) => format!("vendor-{v}"),
-            None => "vendor".to_string(),
-        };
-        let mut outfile = PathBuf::from(outfile);
-        let extension = match &vendor_opts.compression {
-            Compression::Gz => "tar.gz",
-            Compression::Xz => "tar.xz",
-            Compression::Zst | Compression::Zstd => "tar.zst",
-            Compression::Bz2 => "tar.bz",
-            Compression::Not => "tar",
-        };
-
-        if !outfile.set_extension(extension) {
-            return Err(io::Error::other("Unable to set extension"));
-        }
+        merge_into_existing_archive(to_vendor_cargo_config_dir, vendor_opts)?;
+        let outfile = vendor_opts.archive_name(&vendor_opts.compression)?;
         let roast_args = RoastArgs {
             silent: false,
             target: Some(PathBuf::from(&to_vendor_cargo_config_dir)),
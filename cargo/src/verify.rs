@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::archive_manifest::ArchiveManifest;
+use crate::archive_manifest::MANIFEST_FILE_NAME;
+use crate::cli::Opts;
+use crate::dependency_changes::extract_to_tempdir;
+use crate::dependency_changes::find_lockfiles;
+
+/// A file, directory or symlink of an extracted tarball.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveEntry {
+    File { mode: u32, hash: String },
+    Dir { mode: u32 },
+    Symlink { target: PathBuf },
+}
+
+impl fmt::Display for ArchiveEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveEntry::File { mode, hash } => {
+                write!(f, "file with mode {mode:o} and hash {hash}")
+            }
+            ArchiveEntry::Dir { mode } => write!(f, "directory with mode {mode:o}"),
+            ArchiveEntry::Symlink { target } => write!(f, "symlink to {}", target.display()),
+        }
+    }
+}
+
+/// A difference between the given tarball and the regenerated one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveDifference {
+    /// Only in the given tarball.
+    Missing(PathBuf),
+    /// Only in the regenerated tarball.
+    Unexpected(PathBuf),
+    Mode {
+        path: PathBuf,
+        expected: u32,
+        regenerated: u32,
+    },
+    Content {
+        path: PathBuf,
+        expected: ArchiveEntry,
+        regenerated: ArchiveEntry,
+    },
+    /// A difference in what the archive manifests record.
+    Manifest(String),
+}
+
+impl fmt::Display for ArchiveDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveDifference::Missing(path) => {
+                write!(f, "{} is only in the given tarball", path.display())
+            }
+            ArchiveDifference::Unexpected(path) => {
+                write!(f, "{} is only in the regenerated tarball", path.display())
+            }
+            ArchiveDifference::Mode {
+                path,
+                expected,
+                regenerated,
+            } => write!(
+                f,
+                "{} has mode {:o} in the given tarball but {:o} when regenerated",
+                path.display(),
+                expected,
+                regenerated
+            ),
+            ArchiveDifference::Content {
+                path,
+                expected,
+                regenerated,
+            } => write!(
+                f,
+                "{} is a {} in the given tarball but a {} when regenerated",
+                path.display(),
+                expected,
+                regenerated
+            ),
+            ArchiveDifference::Manifest(difference) => {
+                write!(f, "{MANIFEST_FILE_NAME} {difference}")
+            }
+        }
+    }
+}
+
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    metadata.permissions().mode() & 0o7777
+}
+
+fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    entries: &mut BTreeMap<PathBuf, ArchiveEntry>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let metadata = fs::symlink_metadata(&path)?;
+        if metadata.file_type().is_symlink() {
+            entries.insert(
+                relative,
+                ArchiveEntry::Symlink {
+                    target: fs::read_link(&path)?,
+                },
+            );
+        } else if metadata.is_dir() {
+            entries.insert(
+                relative,
+                ArchiveEntry::Dir {
+                    mode: mode_of(&metadata),
+                },
+            );
+            collect_entries(root, &path, entries)?;
+        } else {
+            entries.insert(
+                relative,
+                ArchiveEntry::File {
+                    mode: mode_of(&metadata),
+                    hash: blake3::hash(&fs::read(&path)?).to_hex().to_string(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Paths, modes and content hashes of every entry of an extracted tarball.
+pub fn archive_entries(dir: &Path) -> io::Result<BTreeMap<PathBuf, ArchiveEntry>> {
+    let mut entries = BTreeMap::new();
+    collect_entries(dir, dir, &mut entries)?;
+    Ok(entries)
+}
+
+/// Compare the entries of two tarballs. Modification times are ignored.
+pub fn compare_entries(
+    expected: &BTreeMap<PathBuf, ArchiveEntry>,
+    regenerated: &BTreeMap<PathBuf, ArchiveEntry>,
+) -> Vec<ArchiveDifference> {
+    let paths: BTreeSet<&PathBuf> = expected.keys().chain(regenerated.keys()).collect();
+    let mut differences = Vec::new();
+    for path in paths {
+        let difference = match (expected.get(path), regenerated.get(path)) {
+            (Some(_), None) => ArchiveDifference::Missing(path.clone()),
+            (None, Some(_)) => ArchiveDifference::Unexpected(path.clone()),
+            (Some(expected), Some(regenerated)) if expected == regenerated => continue,
+            (
+                Some(ArchiveEntry::File {
+                    mode: expected_mode,
+                    hash: expected_hash,
+                }),
+                Some(ArchiveEntry::File {
+                    mode: regenerated_mode,
+                    hash: regenerated_hash,
+                }),
+            ) if expected_hash == regenerated_hash => ArchiveDifference::Mode {
+                path: path.clone(),
+                expected: *expected_mode,
+                regenerated: *regenerated_mode,
+            },
+            (
+                Some(ArchiveEntry::Dir { mode: expected }),
+                Some(ArchiveEntry::Dir { mode: regenerated }),
+            ) => ArchiveDifference::Mode {
+                path: path.clone(),
+                expected: *expected,
+                regenerated: *regenerated,
+            },
+            (Some(expected), Some(regenerated)) => ArchiveDifference::Content {
+                path: path.clone(),
+                expected: expected.clone(),
+                regenerated: regenerated.clone(),
+            },
+            (None, None) => continue,
+        };
+        differences.push(difference);
+    }
+    differences
+}
+
+/// Compare what the archive manifests record about the sources, lockfiles
+/// and crates. The options differ by design since verification does not
+/// update. Tarballs without a manifest are not compared.
+pub fn compare_manifests(
+    expected: Option<&ArchiveManifest>,
+    regenerated: Option<&ArchiveManifest>,
+) -> Vec<ArchiveDifference> {
+    let (Some(expected), Some(regenerated)) = (expected, regenerated) else {
+        return Vec::new();
+    };
+    let mut differences = Vec::new();
+    if expected.method != regenerated.method {
+        differences.push(ArchiveDifference::Manifest(format!(
+            "records method {} in the given tarball but {} when regenerated",
+            expected.method, regenerated.method
+        )));
+    }
+    if expected.source != regenerated.source {
+        differences.push(ArchiveDifference::Manifest(format!(
+            "records source {} ({}) in the given tarball but {} ({}) when regenerated",
+            expected.source.name,
+            expected.source.blake3.as_deref().unwrap_or("no hash"),
+            regenerated.source.name,
+            regenerated.source.blake3.as_deref().unwrap_or("no hash")
+        )));
+    }
+    if expected.lockfiles != regenerated.lockfiles {
+        differences.push(ArchiveDifference::Manifest(
+            "records different lockfile hashes in the given tarball than when regenerated"
+                .to_string(),
+        ));
+    }
+    for (crates, other, place) in [
+        (&expected.crates, &regenerated.crates, "the given tarball"),
+        (
+            &regenerated.crates,
+            &expected.crates,
+            "the regenerated tarball",
+        ),
+    ] {
+        for manifest_crate in crates.iter().filter(|&c| !other.contains(c)) {
+            differences.push(ArchiveDifference::Manifest(format!(
+                "records {} {} from {} only in {}",
+                manifest_crate.name, manifest_crate.version, manifest_crate.source, place
+            )));
+        }
+    }
+    differences
+}
+
+/// Copy the lockfiles of the extracted tarball into the sources. They are
+/// what the build uses, so they are what the tarball is verified against.
+pub fn seed_lockfiles(extracted: &Path, setup_workdir: &Path) -> io::Result<()> {
+    let mut lockfiles = Vec::new();
+    find_lockfiles(extracted, &mut lockfiles)?;
+    for lockfile in lockfiles {
+        let relative = lockfile.strip_prefix(extracted).unwrap_or(&lockfile);
+        let target = setup_workdir.join(relative);
+        if !target.parent().is_some_and(Path::is_dir) {
+            warn!(
+                ?relative,
+                "⚠️ Lockfile of the tarball has no place in the sources."
+            );
+            continue;
+        }
+        info!(?relative, "🔬 Using the lockfile of the tarball.");
+        fs::copy(&lockfile, &target)?;
+    }
+    Ok(())
+}
+
+/// Vendor again without updating and respecting the lockfiles of the given
+/// tarball, then compare the result with it. Options with side effects are
+/// turned off, so only a temporary `outdir` is written to.
+pub fn verify_against(opts: &mut Opts, expected: &Path) -> io::Result<()> {
+    if !expected.is_file() {
+        let msg = format!(
+            "Tarball to verify against not found: {}",
+            expected.display()
+        );
+        error!(msg);
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+    }
+    info!(?expected, "🔬 Regenerating the tarball to verify it.");
+    let extracted = extract_to_tempdir(expected)?;
+    let tempdir_for_outdir = tempfile::Builder::new()
+        .prefix(".verify-outdir")
+        .rand_bytes(12)
+        .tempdir()?;
+    opts.verify_against = None;
+    opts.verify_lockfiles_from = Some(extracted.path().to_path_buf());
+    opts.update = false;
+    opts.update_crate.clear();
+    opts.respect_lockfile = true;
+    opts.skip_unchanged = false;
+    opts.changesgenerate = false;
+    opts.changesdependencies = false;
+    opts.merge_into = None;
+    opts.dependency_report = None;
+    opts.policy_report = None;
+    opts.previous_archive = None;
+    opts.outdir = tempdir_for_outdir.path().to_path_buf();
+    opts.run_vendor()?;
+
+    let regenerated = extract_to_tempdir(&opts.outdir.join(opts.archive_name(&opts.compression)?))?;
+    let mut expected_entries = archive_entries(extracted.path())?;
+    let mut regenerated_entries = archive_entries(regenerated.path())?;
+    // NOTE: The manifest records the options of this run. It is compared
+    // separately.
+    expected_entries.remove(Path::new(MANIFEST_FILE_NAME));
+    regenerated_entries.remove(Path::new(MANIFEST_FILE_NAME));
+    let mut differences = compare_entries(&expected_entries, &regenerated_entries);
+    differences.extend(compare_manifests(
+        ArchiveManifest::read(extracted.path())?.as_ref(),
+        ArchiveManifest::read(regenerated.path())?.as_ref(),
+    ));
+    if differences.is_empty() {
+        info!(
+            ?expected,
+            "✅ Tarball matches the regenerated tarball file by file."
+        );
+        return Ok(());
+    }
+    for difference in &differences {
+        error!("🛑 {}", difference);
+    }
+    let msg = format!(
+        "Found {} differences between {} and the regenerated tarball.",
+        differences.len(),
+        expected.display()
+    );
+    error!(msg);
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
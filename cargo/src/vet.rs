@@ -0,0 +1,562 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// Copyright (C) 2024 To all Contributors of this project listed in CONTRIBUTORS.md
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use semver::Version;
+use semver::VersionReq;
+use serde::Deserialize;
+use serde::Serialize;
+#[allow(unused_imports)]
+use tracing::{Level, debug, error, info, trace, warn};
+
+use crate::audit::load_lockfile;
+use crate::cli::LintLevel;
+use crate::cli::Opts;
+
+pub const SAFE_TO_RUN: &str = "safe-to-run";
+pub const SAFE_TO_DEPLOY: &str = "safe-to-deploy";
+/// Default location of the cargo-vet store, relative to the workspace root.
+pub const SUPPLY_CHAIN_DIR: &str = "supply-chain";
+
+// NOTE: Only the parts needed to evaluate audits are read. Publisher based
+// trust needs registry metadata and is not supported.
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Criteria {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Criteria {
+    fn names(&self) -> Vec<String> {
+        match self {
+            Criteria::One(name) => vec![name.clone()],
+            Criteria::Many(names) => names.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct VetConfig {
+    default_criteria: Option<String>,
+    #[serde(default)]
+    policy: BTreeMap<String, VetPolicy>,
+    #[serde(default)]
+    exemptions: BTreeMap<String, Vec<VetExemption>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct VetPolicy {
+    #[serde(default)]
+    dependency_criteria: BTreeMap<String, Criteria>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct VetExemption {
+    version: String,
+    criteria: Criteria,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AuditsFile {
+    #[serde(default)]
+    criteria: BTreeMap<String, CriteriaDefinition>,
+    #[serde(default)]
+    audits: BTreeMap<String, Vec<AuditEntry>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CriteriaDefinition {
+    implies: Option<Criteria>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AuditEntry {
+    criteria: Option<Criteria>,
+    version: Option<String>,
+    delta: Option<String>,
+    violation: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ImportsLock {
+    #[serde(default)]
+    audits: BTreeMap<String, AuditsFile>,
+}
+
+/// An edge in the audit graph of a crate. A full audit or an exemption starts
+/// from no version at all.
+#[derive(Debug, Clone)]
+struct AuditEdge {
+    from: Option<Version>,
+    to: Version,
+    criteria: BTreeSet<String>,
+    exemption: bool,
+}
+
+/// Every audit, exemption and criteria definition known for the sources.
+#[derive(Debug, Default)]
+pub struct AuditSet {
+    pub files: Vec<PathBuf>,
+    default_criteria: Option<String>,
+    /// Criteria by the crate that declares the dependency and the name of
+    /// the dependency.
+    dependency_criteria: BTreeMap<(String, String), BTreeSet<String>>,
+    implies: BTreeMap<String, BTreeSet<String>>,
+    edges: BTreeMap<String, Vec<AuditEdge>>,
+    violations: BTreeMap<String, Vec<(VersionReq, String)>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VetStatus {
+    Vetted,
+    Exempted,
+    WeakerCriteria,
+    Unvetted,
+    Violation,
+}
+
+/// The result of checking one crate version against the audits.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct VetFinding {
+    pub name: String,
+    pub version: String,
+    pub status: VetStatus,
+    pub required: Vec<String>,
+    /// Criteria the crate version is vetted for without exemptions.
+    pub achieved: Vec<String>,
+    pub violated_by: Option<String>,
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(path: &Path) -> io::Result<T> {
+    let content = fs::read_to_string(path)?;
+    toml::from_str(&content).map_err(|err| {
+        let msg = format!("Invalid cargo-vet file {}: {}", path.display(), err);
+        error!(msg);
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    })
+}
+
+fn parse_version(name: &str, version: &str) -> Option<Version> {
+    let parsed = Version::parse(version.trim()).ok();
+    if parsed.is_none() {
+        warn!(
+            "⚠️ Skipping audit of {} with invalid version `{}`.",
+            name, version
+        );
+    }
+    parsed
+}
+
+impl AuditSet {
+    fn add_audits(&mut self, origin: &str, audits: AuditsFile) {
+        for (name, definition) in audits.criteria {
+            let implied = definition
+                .implies
+                .map(|criteria| criteria.names())
+                .unwrap_or_default();
+            self.implies.entry(name).or_default().extend(implied);
+        }
+        for (name, entries) in audits.audits {
+            for entry in entries {
+                if let Some(violation) = entry.violation {
+                    match VersionReq::parse(&violation) {
+                        Ok(requirement) => self
+                            .violations
+                            .entry(name.clone())
+                            .or_default()
+                            .push((requirement, origin.to_string())),
+                        Err(err) => warn!(
+                            ?err,
+                            "⚠️ Skipping violation of {} with invalid requirement `{}`.",
+                            name,
+                            violation
+                        ),
+                    }
+                    continue;
+                }
+                let criteria = entry
+                    .criteria
+                    .map(|criteria| criteria.names())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+                let (from, to) = match (entry.version, entry.delta) {
+                    (Some(version), _) => (None, version),
+                    (None, Some(delta)) => {
+                        let Some((from, to)) = delta.split_once("->") else {
+                            warn!(
+                                "⚠️ Skipping audit of {} with invalid delta `{}`.",
+                                name, delta
+                            );
+                            continue;
+                        };
+                        let Some(from) = parse_version(&name, from) else {
+                            continue;
+                        };
+                        (Some(from), to.to_string())
+                    }
+                    (None, None) => continue,
+                };
+                let Some(to) = parse_version(&name, &to) else {
+                    continue;
+                };
+                self.edges.entry(name.clone()).or_default().push(AuditEdge {
+                    from,
+                    to,
+                    criteria,
+                    exemption: false,
+                });
+            }
+        }
+    }
+
+    fn add_config(&mut self, config: VetConfig) {
+        if config.default_criteria.is_some() {
+            self.default_criteria = config.default_criteria;
+        }
+        for (dependent, policy) in config.policy {
+            // NOTE: Policies of cargo-vet may be keyed by `name:version`.
+            let dependent = dependent
+                .split_once(':')
+                .map_or(dependent.as_str(), |(name, _)| name)
+                .to_string();
+            for (name, criteria) in policy.dependency_criteria {
+                self.dependency_criteria
+                    .entry((dependent.clone(), name))
+                    .or_default()
+                    .extend(criteria.names());
+            }
+        }
+        for (name, exemptions) in config.exemptions {
+            for exemption in exemptions {
+                let Some(to) = parse_version(&name, &exemption.version) else {
+                    continue;
+                };
+                self.edges.entry(name.clone()).or_default().push(AuditEdge {
+                    from: None,
+                    to,
+                    criteria: exemption.criteria.names().into_iter().collect(),
+                    exemption: true,
+                });
+            }
+        }
+    }
+
+    /// Load the cargo-vet store of the sources, if any, and the extra audit
+    /// files maintained by the distribution.
+    pub fn load(store: Option<&Path>, extra_audits: &[PathBuf]) -> io::Result<Self> {
+        let mut audit_set = AuditSet::default();
+        if let Some(store) = store {
+            let config = store.join("config.toml");
+            if config.is_file() {
+                audit_set.add_config(parse(&config)?);
+                audit_set.files.push(config);
+            }
+            let audits = store.join("audits.toml");
+            if audits.is_file() {
+                audit_set.add_audits(&audits.display().to_string(), parse(&audits)?);
+                audit_set.files.push(audits);
+            }
+            let imports = store.join("imports.lock");
+            if imports.is_file() {
+                let imports_lock: ImportsLock = parse(&imports)?;
+                for (import, audits) in imports_lock.audits {
+                    let origin = format!("{} ({})", imports.display(), import);
+                    audit_set.add_audits(&origin, audits);
+                }
+                audit_set.files.push(imports);
+            }
+        }
+        for audits in extra_audits {
+            audit_set.add_audits(&audits.display().to_string(), parse(audits)?);
+            audit_set.files.push(audits.clone());
+        }
+        Ok(audit_set)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// All criteria implied by the given ones, including themselves.
+    fn closure(&self, criteria: &BTreeSet<String>) -> BTreeSet<String> {
+        let mut closed = BTreeSet::new();
+        let mut pending: Vec<String> = criteria.iter().cloned().collect();
+        while let Some(criterion) = pending.pop() {
+            if !closed.insert(criterion.clone()) {
+                continue;
+            }
+            if criterion == SAFE_TO_DEPLOY {
+                pending.push(SAFE_TO_RUN.to_string());
+            }
+            if let Some(implied) = self.implies.get(&criterion) {
+                pending.extend(implied.iter().cloned());
+            }
+        }
+        closed
+    }
+
+    /// Like cargo-vet, `dependency-criteria` only apply to the dependencies
+    /// of the crate whose policy declares them.
+    fn required_criteria(
+        &self,
+        name: &str,
+        dependents: &BTreeSet<String>,
+        required: Option<&str>,
+    ) -> BTreeSet<String> {
+        if let Some(required) = required {
+            return BTreeSet::from([required.to_string()]);
+        }
+        let declared: BTreeSet<String> = dependents
+            .iter()
+            .filter_map(|dependent| {
+                self.dependency_criteria
+                    .get(&(dependent.clone(), name.to_string()))
+            })
+            .flatten()
+            .cloned()
+            .collect();
+        if declared.is_empty() {
+            BTreeSet::from([self
+                .default_criteria
+                .clone()
+                .unwrap_or(SAFE_TO_DEPLOY.to_string())])
+        } else {
+            declared
+        }
+    }
+
+    /// Whether a chain of audits, all of them for `criterion`, leads up to
+    /// `version`.
+    fn reaches(&self, edges: &[AuditEdge], version: &Version, criterion: &str) -> bool {
+        let usable: Vec<&AuditEdge> = edges
+            .iter()
+            .filter(|edge| self.closure(&edge.criteria).contains(criterion))
+            .collect();
+        let mut reached: BTreeSet<&Version> = usable
+            .iter()
+            .filter(|edge| edge.from.is_none())
+            .map(|edge| &edge.to)
+            .collect();
+        loop {
+            let before = reached.len();
+            for edge in &usable {
+                if edge
+                    .from
+                    .as_ref()
+                    .is_some_and(|from| reached.contains(from))
+                {
+                    reached.insert(&edge.to);
+                }
+            }
+            if reached.len() == before {
+                return reached.contains(version);
+            }
+        }
+    }
+
+    fn evaluate(
+        &self,
+        name: &str,
+        version: &Version,
+        dependents: &BTreeSet<String>,
+        required: Option<&str>,
+    ) -> VetFinding {
+        let required_criteria = self.required_criteria(name, dependents, required);
+        let mut finding = VetFinding {
+            name: name.to_string(),
+            version: version.to_string(),
+            status: VetStatus::Unvetted,
+            required: required_criteria.iter().cloned().collect(),
+            achieved: Vec::new(),
+            violated_by: None,
+        };
+        if let Some((_, origin)) = self
+            .violations
+            .get(name)
+            .and_then(|violations| violations.iter().find(|(req, _)| req.matches(version)))
+        {
+            finding.status = VetStatus::Violation;
+            finding.violated_by = Some(origin.clone());
+            return finding;
+        }
+        let edges = self.edges.get(name).cloned().unwrap_or_default();
+        let audits: Vec<AuditEdge> = edges
+            .iter()
+            .filter(|edge| !edge.exemption)
+            .cloned()
+            .collect();
+        let known_criteria: BTreeSet<String> = edges
+            .iter()
+            .flat_map(|edge| self.closure(&edge.criteria))
+            .collect();
+        finding.achieved = known_criteria
+            .iter()
+            .filter(|criterion| self.reaches(&audits, version, criterion))
+            .cloned()
+            .collect();
+        finding.status = if required_criteria
+            .iter()
+            .all(|criterion| finding.achieved.contains(criterion))
+        {
+            VetStatus::Vetted
+        } else if required_criteria
+            .iter()
+            .all(|criterion| self.reaches(&edges, version, criterion))
+        {
+            VetStatus::Exempted
+        } else if !finding.achieved.is_empty() {
+            VetStatus::WeakerCriteria
+        } else {
+            VetStatus::Unvetted
+        };
+        finding
+    }
+}
+
+/// The cargo-vet store of the sources. cargo-vet allows to move it with
+/// `store.path` in `[workspace.metadata.vet]` or `[package.metadata.vet]`.
+pub fn find_vet_store(custom_root: &Path) -> Option<PathBuf> {
+    let manifest = fs::read_to_string(custom_root.join("Cargo.toml")).ok();
+    let configured = manifest
+        .and_then(|manifest| toml::from_str::<toml::Table>(&manifest).ok())
+        .and_then(|manifest| {
+            ["workspace", "package"].into_iter().find_map(|table| {
+                manifest
+                    .get(table)?
+                    .get("metadata")?
+                    .get("vet")?
+                    .get("store")?
+                    .get("path")?
+                    .as_str()
+                    .map(PathBuf::from)
+            })
+        });
+    let store = custom_root.join(configured.unwrap_or(PathBuf::from(SUPPLY_CHAIN_DIR)));
+    store.is_dir().then_some(store)
+}
+
+/// Check every crate version of the lockfiles against the audits. Returns
+/// nothing if there is neither a cargo-vet store nor an extra audit file.
+pub fn check_vet(
+    custom_root: &Path,
+    lockfiles: &[impl AsRef<Path>],
+    opts: &Opts,
+) -> io::Result<Option<Vec<VetFinding>>> {
+    if opts.cargo_vet == LintLevel::Allow {
+        return Ok(None);
+    }
+    let store = find_vet_store(custom_root);
+    let audit_set = AuditSet::load(store.as_deref(), &opts.vet_audits)?;
+    if audit_set.is_empty() {
+        debug!("No cargo-vet store or audit files found.");
+        return Ok(None);
+    }
+    for file in &audit_set.files {
+        info!(
+            "🔍 Using cargo-vet audits from {}",
+            file.strip_prefix(custom_root).unwrap_or(file).display()
+        );
+    }
+    // NOTE: Local crates are first-party and don't need audits.
+    let mut third_party: BTreeSet<(String, Version)> = BTreeSet::new();
+    let mut dependents: BTreeMap<(String, Version), BTreeSet<String>> = BTreeMap::new();
+    for lockfile_path in lockfiles {
+        for package in load_lockfile(lockfile_path.as_ref())?.packages {
+            for dependency in &package.dependencies {
+                dependents
+                    .entry((dependency.name.to_string(), dependency.version.clone()))
+                    .or_default()
+                    .insert(package.name.to_string());
+            }
+            if package.source.is_some() {
+                third_party.insert((package.name.to_string(), package.version.clone()));
+            }
+        }
+    }
+    let no_dependents = BTreeSet::new();
+    let findings = third_party
+        .iter()
+        .map(|key| {
+            audit_set.evaluate(
+                &key.0,
+                &key.1,
+                dependents.get(key).unwrap_or(&no_dependents),
+                opts.vet_criteria.as_deref(),
+            )
+        })
+        .collect();
+    Ok(Some(findings))
+}
+
+/// Log the crates that are not fully vetted. Returns how many are denied.
+pub fn report_vet(findings: &[VetFinding], level: LintLevel) -> usize {
+    let count = |status| {
+        findings
+            .iter()
+            .filter(|finding| finding.status == status)
+            .count()
+    };
+    info!(
+        "🔍 {} crates vetted, {} exempted, {} vetted with weaker criteria, {} unvetted, {} violations.",
+        count(VetStatus::Vetted),
+        count(VetStatus::Exempted),
+        count(VetStatus::WeakerCriteria),
+        count(VetStatus::Unvetted),
+        count(VetStatus::Violation)
+    );
+    let mut denied = 0;
+    for finding in findings {
+        let message = match finding.status {
+            VetStatus::Vetted | VetStatus::Exempted => continue,
+            VetStatus::WeakerCriteria => format!(
+                "{} {} is only vetted for {} but {} is required.",
+                finding.name,
+                finding.version,
+                finding.achieved.join(", "),
+                finding.required.join(", ")
+            ),
+            VetStatus::Unvetted => format!(
+                "{} {} is not vetted for {}.",
+                finding.name,
+                finding.version,
+                finding.required.join(", ")
+            ),
+            VetStatus::Violation => format!(
+                "{} {} is marked as a violation in {}.",
+                finding.name,
+                finding.version,
+                finding.violated_by.as_deref().unwrap_or_default()
+            ),
+        };
+        if level == LintLevel::Deny || finding.status == VetStatus::Violation {
+            denied += 1;
+            error!("🛑 {}", message);
+        } else {
+            warn!("⚠️ {}", message);
+        }
+    }
+    denied
+}
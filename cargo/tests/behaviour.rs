@@ -2,11 +2,14 @@
 #![allow(unused_imports)]
 
 use blake3::Hasher;
+use clap::Parser;
 use libroast::{
     common::Compression,
     operations::{cli::RawArgs, raw::raw_opts},
 };
-use obs_service_cargo::cli::{self, Method, VendorArgs};
+use obs_service_cargo::archive_manifest::ArchiveManifest;
+use obs_service_cargo::cli::{self, LintLevel, Method, RegistryLayout, VendorArgs};
+use obs_service_cargo::staleness::check_staleness;
 use rand::prelude::*;
 use std::{io, path::PathBuf};
 use test_log::test;
@@ -16,6 +19,7 @@ use tracing::info;
 
 const MANIFEST_DIR: &str = std::env!("CARGO_MANIFEST_DIR", "No such manifest dir");
 
+#[allow(clippy::manual_filter, clippy::useless_borrows_in_formatting)]
 async fn another_vendor_helper(source: &str, update: bool) -> io::Result<PathBuf> {
     let mut rng = rand::rng();
     let random_tag: u8 = rng.random();
@@ -45,6 +49,7 @@ async fn another_vendor_helper(source: &str, update: bool) -> io::Result<PathBuf
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -52,6 +57,7 @@ async fn another_vendor_helper(source: &str, update: bool) -> io::Result<PathBuf
         versionrewriteregex: None,
         versionrewritepattern: None,
         method: Method::Vendor,
+        registry_layout: RegistryLayout::CargoHome,
         src: outfile.to_string_lossy().to_string(),
         custom_root: None,
         no_root_manifest: Some(false),
@@ -64,6 +70,36 @@ async fn another_vendor_helper(source: &str, update: bool) -> io::Result<PathBuf
         respect_lockfile: false,
         i_accept_the_risk: vec![],
         update_crate: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: Some(vendor_specific_args),
     };
 
@@ -122,6 +158,7 @@ async fn lockfile_does_change_if_update_is_true() -> io::Result<()> {
     Ok(())
 }
 
+#[allow(clippy::manual_filter, clippy::useless_borrows_in_formatting)]
 async fn vendor_source(source: &str, filter: bool) -> io::Result<PathBuf> {
     let mut rng = rand::rng();
     let random_tag: u8 = rng.random();
@@ -151,6 +188,7 @@ async fn vendor_source(source: &str, filter: bool) -> io::Result<PathBuf> {
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -158,6 +196,7 @@ async fn vendor_source(source: &str, filter: bool) -> io::Result<PathBuf> {
         versionrewriteregex: None,
         versionrewritepattern: None,
         method: Method::Vendor,
+        registry_layout: RegistryLayout::CargoHome,
         src: outfile.to_string_lossy().to_string(),
         custom_root: None,
         no_root_manifest: None,
@@ -170,6 +209,36 @@ async fn vendor_source(source: &str, filter: bool) -> io::Result<PathBuf> {
         respect_lockfile: false,
         i_accept_the_risk: vec![],
         update_crate: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: Some(vendor_specific_args),
     };
 
@@ -246,6 +315,7 @@ async fn filter_vendor_sources() -> io::Result<()> {
 }
 
 #[test(tokio::test)]
+#[allow(clippy::manual_filter, clippy::useless_borrows_in_formatting)]
 async fn vendor_registry_test_with_no_root_manifest() -> io::Result<()> {
     let source = "https://github.com/ibm-s390-linux/s390-tools/archive/refs/tags/v2.29.0.tar.gz";
     let mut rng = rand::rng();
@@ -276,6 +346,7 @@ async fn vendor_registry_test_with_no_root_manifest() -> io::Result<()> {
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -286,11 +357,42 @@ async fn vendor_registry_test_with_no_root_manifest() -> io::Result<()> {
         update_crate: vec![],
         no_root_manifest: Some(true),
         method: Method::Registry,
+        registry_layout: RegistryLayout::CargoHome,
         src: outfile.to_string_lossy().to_string(),
         compression: Compression::default(),
         tag: Some(random_tag.clone()),
         manifest_path: [PathBuf::from("rust/pvsecret/Cargo.toml")].to_vec(),
         update: true,
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: Some(vendor_specific_args),
         respect_lockfile: false,
         outdir: outdir.to_path_buf(),
@@ -336,6 +438,7 @@ async fn vendor_registry_test_with_no_root_manifest() -> io::Result<()> {
 }
 
 #[test(tokio::test)]
+#[allow(clippy::manual_filter, clippy::useless_borrows_in_formatting)]
 async fn manifest_paths_with_vendor() -> io::Result<()> {
     let source = "https://github.com/influxdata/flux/archive/refs/tags/v0.194.4.tar.gz";
     let mut rng = rand::rng();
@@ -362,6 +465,7 @@ async fn manifest_paths_with_vendor() -> io::Result<()> {
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -372,6 +476,7 @@ async fn manifest_paths_with_vendor() -> io::Result<()> {
         no_root_manifest: None,
         custom_root: None,
         method: Method::Vendor,
+        registry_layout: RegistryLayout::CargoHome,
         src: outfile.to_string_lossy().to_string(),
         compression: Compression::default(),
         tag: Some(random_tag.clone()),
@@ -381,6 +486,36 @@ async fn manifest_paths_with_vendor() -> io::Result<()> {
         outdir: outdir.to_path_buf(),
         color: clap::ColorChoice::Auto,
         i_accept_the_risk: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: None,
     };
 
@@ -415,6 +550,7 @@ async fn manifest_paths_with_vendor() -> io::Result<()> {
 }
 
 #[test(tokio::test)]
+#[allow(clippy::manual_filter, clippy::useless_borrows_in_formatting)]
 async fn custom_root_test_1() -> io::Result<()> {
     let source = "https://github.com/influxdata/flux/archive/refs/tags/v0.194.4.tar.gz";
     let mut rng = rand::rng();
@@ -441,6 +577,7 @@ async fn custom_root_test_1() -> io::Result<()> {
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -452,6 +589,7 @@ async fn custom_root_test_1() -> io::Result<()> {
         respect_lockfile: false,
         custom_root: Some("libflux".to_string()),
         method: Method::Vendor,
+        registry_layout: RegistryLayout::CargoHome,
         src: outfile.to_string_lossy().to_string(),
         compression: Compression::default(),
         tag: Some(random_tag.clone()),
@@ -460,6 +598,36 @@ async fn custom_root_test_1() -> io::Result<()> {
         outdir: outdir.to_path_buf(),
         color: clap::ColorChoice::Auto,
         i_accept_the_risk: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: None,
     };
 
@@ -494,6 +662,7 @@ async fn custom_root_test_1() -> io::Result<()> {
 }
 
 #[test(tokio::test)]
+#[allow(clippy::manual_filter, clippy::useless_borrows_in_formatting)]
 async fn custom_root_test_2() -> io::Result<()> {
     let source = "https://github.com/influxdata/flux/archive/refs/tags/v0.194.4.tar.gz";
     let mut rng = rand::rng();
@@ -520,6 +689,7 @@ async fn custom_root_test_2() -> io::Result<()> {
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -531,6 +701,7 @@ async fn custom_root_test_2() -> io::Result<()> {
         respect_lockfile: false,
         custom_root: Some("libflux".to_string()),
         method: Method::Registry,
+        registry_layout: RegistryLayout::CargoHome,
         src: outfile.to_string_lossy().to_string(),
         compression: Compression::default(),
         tag: Some(random_tag.clone()),
@@ -539,6 +710,36 @@ async fn custom_root_test_2() -> io::Result<()> {
         outdir: outdir.to_path_buf(),
         color: clap::ColorChoice::Auto,
         i_accept_the_risk: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: None,
     };
 
@@ -575,6 +776,7 @@ async fn custom_root_test_2() -> io::Result<()> {
 }
 
 #[test(tokio::test)]
+#[allow(clippy::manual_filter, clippy::useless_borrows_in_formatting)]
 async fn custom_root_test_3() -> io::Result<()> {
     let source = "https://github.com/huggingface/tokenizers/archive/refs/tags/v0.21.0.tar.gz";
     let mut rng = rand::rng();
@@ -606,6 +808,7 @@ async fn custom_root_test_3() -> io::Result<()> {
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -617,6 +820,7 @@ async fn custom_root_test_3() -> io::Result<()> {
         respect_lockfile: false,
         custom_root: None,
         method: Method::Registry,
+        registry_layout: RegistryLayout::CargoHome,
         src: outfile.to_string_lossy().to_string(),
         compression: Compression::default(),
         tag: Some(random_tag.clone()),
@@ -625,6 +829,36 @@ async fn custom_root_test_3() -> io::Result<()> {
         outdir: outdir.to_path_buf(),
         color: clap::ColorChoice::Auto,
         i_accept_the_risk: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: None,
     };
 
@@ -677,6 +911,7 @@ fn vendor_git_source_of_package_itself_with_vendor_method() -> io::Result<()> {
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -688,6 +923,7 @@ fn vendor_git_source_of_package_itself_with_vendor_method() -> io::Result<()> {
         respect_lockfile: false,
         custom_root: None,
         method: Method::Vendor,
+        registry_layout: RegistryLayout::CargoHome,
         src: url.to_string(),
         compression: Compression::default(),
         tag: None,
@@ -696,6 +932,36 @@ fn vendor_git_source_of_package_itself_with_vendor_method() -> io::Result<()> {
         outdir: outdir.to_path_buf(),
         color: clap::ColorChoice::Auto,
         i_accept_the_risk: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: None,
     };
     let res = opt.run_vendor();
@@ -720,6 +986,7 @@ fn vendor_git_source_of_package_itself_with_registry_method() -> io::Result<()>
         changesauthor: None,
         changesemail: None,
         changesoutfile: None,
+        changesdependencies: false,
         set_version: None,
         set_name: None,
         exclude: None,
@@ -731,6 +998,7 @@ fn vendor_git_source_of_package_itself_with_registry_method() -> io::Result<()>
         respect_lockfile: false,
         custom_root: None,
         method: Method::Registry,
+        registry_layout: RegistryLayout::CargoHome,
         src: url.to_string(),
         compression: Compression::default(),
         tag: None,
@@ -739,9 +1007,2180 @@ fn vendor_git_source_of_package_itself_with_registry_method() -> io::Result<()>
         outdir: outdir.to_path_buf(),
         color: clap::ColorChoice::Auto,
         i_accept_the_risk: vec![],
+        deny_floating_git_refs: false,
+        allowed_git_host: vec![],
+        allowed_registry: vec![],
+        allowed_git: vec![],
+        policy: None,
+        deny_crate: vec![],
+        multiple_versions: None,
+        policy_report: None,
+        trust_upstream_audit_config: false,
+        cargo_vet: LintLevel::Warn,
+        vet_audits: vec![],
+        vet_criteria: None,
+        previous_archive: None,
+        dependency_report: None,
+        upstream_policy: None,
+        upstream_hints: None,
+        verify_against: None,
+        from_service: None,
+        config: None,
+        command: None,
+        crate_cache: None,
+        offline: false,
+        local_registry: None,
+        alternate_registry: vec![],
+        merge_into: None,
+        verify_lockfiles_from: None,
+        extracted_src: None,
+        skip_unchanged: false,
+        dry_run: false,
+        check: false,
         vendor_specific_args: None,
     };
     let res = opt.run_vendor();
     assert!(res.is_ok());
     Ok(())
 }
+
+fn write_fixture(root: &std::path::Path, files: &[(&str, &str)]) -> io::Result<()> {
+    for (path, content) in files {
+        let path = root.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn path_dependencies_outside_source_tree_are_reported() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["app", "missing-member"]
+
+[patch.crates-io]
+foo = { path = "../../foo" }
+"#,
+            ),
+            (
+                "app/Cargo.toml",
+                r#"[package]
+name = "app"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+shared = { path = "../vendor/shared" }
+"#,
+            ),
+            ("app/src/main.rs", "fn main() {}\n"),
+            (
+                ".gitmodules",
+                "[submodule \"shared\"]\n\tpath = vendor/shared\n",
+            ),
+        ],
+    )?;
+
+    let manifests = vec![src.join("Cargo.toml")];
+    let issues = obs_service_cargo::preflight::analyse_path_dependencies(&src, &src, &manifests)?;
+    let rendered: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+    info!(?rendered);
+    assert_eq!(issues.len(), 3);
+    assert!(
+        rendered
+            .iter()
+            .any(|issue| issue.starts_with("Cargo.toml:2: `missing-member`"))
+    );
+    assert!(rendered.iter().any(|issue| {
+        issue.starts_with("Cargo.toml:5: `foo` in `[patch.crates-io]`")
+            && issue.ends_with("outside of the extracted sources")
+    }));
+    assert!(
+        rendered
+            .iter()
+            .any(|issue| issue.starts_with("app/Cargo.toml:7: `shared`")
+                && issue.contains("git submodule `vendor/shared`"))
+    );
+
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+    ]);
+    assert!(opt.run_vendor().is_err());
+    Ok(())
+}
+
+fn git_fixture(root: &std::path::Path, name: &str) -> io::Result<url::Url> {
+    git_fixture_at_version(root, name, "0.1.0")
+}
+
+fn git_fixture_at_version(
+    root: &std::path::Path,
+    name: &str,
+    version: &str,
+) -> io::Result<url::Url> {
+    write_fixture(
+        root,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"{name}\"\nversion = \"{version}\"\nedition = \"2021\"\n"
+                ),
+            ),
+            ("src/lib.rs", "pub fn hello() {}\n"),
+        ],
+    )?;
+    let status = std::process::Command::new("git")
+        .args(["init", "--quiet", "--initial-branch=main"])
+        .current_dir(root)
+        .status()?;
+    assert!(status.success());
+    git_commit_all(root)?;
+    Ok(url::Url::from_directory_path(root).unwrap())
+}
+
+fn git_commit_all(root: &std::path::Path) -> io::Result<()> {
+    for args in [
+        vec!["add", "."],
+        vec![
+            "-c",
+            "user.name=obs",
+            "-c",
+            "user.email=obs@localhost",
+            "commit",
+            "--quiet",
+            "-m",
+            "update",
+        ],
+    ] {
+        let status = std::process::Command::new("git")
+            .args(&args)
+            .current_dir(root)
+            .status()?;
+        assert!(status.success());
+    }
+    Ok(())
+}
+
+#[test]
+fn git_dependencies_following_a_branch_are_denied() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let repository = git_fixture(&tmp_binding.path().join("gitdep"), "gitdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{repository}\", branch = \"main\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+    ];
+    let mut opt = cli::Opts::parse_from(args);
+    opt.deny_floating_git_refs = true;
+    assert!(opt.run_vendor().is_err());
+
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    assert!(outdir.join("vendor.tar.zst").is_file());
+    Ok(())
+}
+
+#[test]
+fn git_dependencies_from_sources_not_in_the_policy_are_denied() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let repository = git_fixture(&tmp_binding.path().join("gitdep"), "gitdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{repository}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let policy = tmp_binding.path().join("policy.toml");
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--policy",
+        &policy.to_string_lossy(),
+    ];
+
+    std::fs::write(&policy, "[sources]\nallow-git-hosts = [\"github.com\"]\n")?;
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_err());
+
+    std::fs::write(&policy, "[sources]\nallow-gits = [\"github.com\"]\n")?;
+    let err = obs_service_cargo::policy::Policy::load(&policy).unwrap_err();
+    assert!(err.to_string().contains("unknown field `allow-gits`"));
+
+    // A look-alike of an allowed prefix is not allowed.
+    std::fs::write(
+        &policy,
+        format!(
+            "[sources]\nallow-git = [\"file://{}/git/\"]\n",
+            tmp_binding.path().display()
+        ),
+    )?;
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_err());
+
+    std::fs::write(
+        &policy,
+        format!(
+            "[sources]\nallow-git = [\"file://{}\"]\n",
+            tmp_binding.path().display()
+        ),
+    )?;
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    Ok(())
+}
+
+#[test]
+fn banned_crates_and_multiple_versions_are_reported() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let old = git_fixture_at_version(&tmp_binding.path().join("old"), "dup", "0.1.0")?;
+    let new = git_fixture_at_version(&tmp_binding.path().join("new"), "dup", "0.2.0")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nold = {{ package = \"dup\", git = \"{old}\" }}\nnew = {{ package = \"dup\", git = \"{new}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let policy = tmp_binding.path().join("policy.toml");
+    let report = tmp_binding.path().join("report.json");
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--policy",
+        &policy.to_string_lossy(),
+        "--policy-report",
+        &report.to_string_lossy(),
+    ];
+
+    std::fs::write(&policy, "")?;
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(content["multiple-versions"], "warn");
+    assert_eq!(content["duplicates"][0]["name"], "dup");
+    assert_eq!(content["duplicates"][0]["versions"][0]["version"], "0.1.0");
+    assert_eq!(
+        content["duplicates"][0]["versions"][0]["dependents"][0],
+        "app 0.1.0"
+    );
+    assert_eq!(content["duplicates"][0]["versions"][1]["version"], "0.2.0");
+
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&["--multiple-versions", "deny"]));
+    assert!(opt.run_vendor().is_err());
+
+    std::fs::write(
+        &policy,
+        "[bans]\nmultiple-versions = \"deny\"\nskip = [\"dup@=0.1.0\"]\n",
+    )?;
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+
+    std::fs::write(
+        &policy,
+        "[bans]\ndeny = [{ crate = \"dup@<0.2\", reason = \"Replaced by dup 0.2\" }]\n",
+    )?;
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_err());
+    let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(content["banned"].as_array().map(Vec::len), Some(1));
+    assert_eq!(content["banned"][0]["version"], "0.1.0");
+    assert_eq!(content["banned"][0]["reason"], "Replaced by dup 0.2");
+
+    std::fs::write(&policy, "[bans]\ndeny = [\"dup@not-a-version\"]\n")?;
+    let err = obs_service_cargo::policy::Policy::load(&policy).unwrap_err();
+    assert!(err.to_string().contains("Invalid policy file"));
+    Ok(())
+}
+
+#[test]
+fn upstream_audit_config_is_shown_and_applied_when_trusted() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let repository = git_fixture(&tmp_binding.path().join("gitdep"), "gitdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{repository}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+            (
+                "deny.toml",
+                "[graph]\nall-features = true\n\n[advisories]\nignore = [\n    { id = \"RUSTSEC-2024-0001\", reason = \"Not reachable from our code\" },\n    { crate = \"atty\", reason = \"Unmaintained\" },\n]\n\n[bans]\ndeny = [{ crate = \"gitdep\", wrappers = [\"app\"], reason = \"Use the crates.io release\" }]\n\n[licenses]\nallow = [\"MIT\"]\nexceptions = [{ allow = [\"Unicode-3.0\"], crate = \"unicode-ident\" }]\n",
+            ),
+            (
+                ".cargo/audit.toml",
+                "[advisories]\nignore = [\"RUSTSEC-2024-0002\"]\n",
+            ),
+        ],
+    )?;
+
+    let config = obs_service_cargo::upstream_config::load_upstream_audit_config(&src, &src, true)?;
+    assert_eq!(config.files.len(), 2);
+    assert_eq!(config.ignores.len(), 2);
+    assert_eq!(config.ignores[0].id, "RUSTSEC-2024-0001");
+    assert_eq!(
+        config.ignores[0].reason.as_deref(),
+        Some("Not reachable from our code")
+    );
+    assert_eq!(config.ignored_crates, vec!["atty".to_string()]);
+    assert_eq!(config.policy.bans.deny[0].name, "gitdep");
+    assert_eq!(
+        config.license_exceptions,
+        vec!["unicode-ident: Unicode-3.0".to_string()]
+    );
+
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+    ];
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    assert!(opt.i_accept_the_risk.is_empty());
+
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&[
+        "--trust-upstream-audit-config",
+        "true",
+        "--i-accept-the-risk",
+        "RUSTSEC-2024-0002",
+    ]));
+    assert!(opt.run_vendor().is_err());
+    assert_eq!(
+        opt.i_accept_the_risk,
+        vec![
+            "RUSTSEC-2024-0002".to_string(),
+            "RUSTSEC-2024-0001".to_string()
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn upstream_sources_do_not_widen_the_policy() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let repository = git_fixture(&tmp_binding.path().join("gitdep"), "gitdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{repository}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+            (
+                "deny.toml",
+                &format!(
+                    "[sources]\nallow-registry = [\"https://example.com/index\"]\nallow-git = [\"{repository}\"]\n"
+                ),
+            ),
+        ],
+    )?;
+
+    let config = obs_service_cargo::upstream_config::load_upstream_audit_config(&src, &src, true)?;
+    assert_eq!(config.allow_git, vec![repository.to_string()]);
+
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--allowed-git-host",
+        "github.com",
+        "--trust-upstream-audit-config",
+        "true",
+    ]);
+    assert!(opt.run_vendor().is_err());
+    let policy = obs_service_cargo::policy::Policy::from_opts(&opt)?;
+    assert!(policy.sources.allow_git.is_empty());
+    assert_eq!(policy.sources.allow_registry, vec!["crates-io".to_string()]);
+    Ok(())
+}
+
+#[test]
+fn crates_are_checked_against_cargo_vet_audits() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let mut dependencies = String::new();
+    for name in ["alpha", "beta", "gamma", "delta", "epsilon"] {
+        let repository = git_fixture(&tmp_binding.path().join(name), name)?;
+        dependencies.push_str(&format!("{name} = {{ git = \"{repository}\" }}\n"));
+    }
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n{dependencies}"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+            (
+                "supply-chain/audits.toml",
+                "[criteria.reviewed]\ndescription = \"Reviewed line by line\"\nimplies = \"safe-to-deploy\"\n\n[[audits.alpha]]\nwho = \"Someone <someone@example.com>\"\ncriteria = \"reviewed\"\nversion = \"0.1.0\"\n\n[[audits.beta]]\nwho = \"Someone <someone@example.com>\"\ncriteria = \"safe-to-run\"\nversion = \"0.1.0\"\n\n[[audits.delta]]\nwho = \"Someone <someone@example.com>\"\ncriteria = \"safe-to-deploy\"\nversion = \"0.0.9\"\n",
+            ),
+            (
+                "supply-chain/config.toml",
+                "[cargo-vet]\nversion = \"0.10\"\n\n[policy.other]\ndependency-criteria = { beta = \"safe-to-run\" }\n\n[[exemptions.gamma]]\nversion = \"0.1.0\"\ncriteria = \"safe-to-deploy\"\n",
+            ),
+        ],
+    )?;
+    let distro_audits = tmp_binding.path().join("distro-audits.toml");
+    std::fs::write(
+        &distro_audits,
+        "[[audits.delta]]\nwho = \"Packager <packager@example.com>\"\ncriteria = \"safe-to-deploy\"\ndelta = \"0.0.9 -> 0.1.0\"\n",
+    )?;
+    let report = tmp_binding.path().join("report.json");
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--vet-audits",
+        &distro_audits.to_string_lossy(),
+        "--policy-report",
+        &report.to_string_lossy(),
+    ];
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    let statuses: Vec<(String, String)> = content["vet"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|finding| {
+            (
+                finding["name"].as_str().unwrap().to_string(),
+                finding["status"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("alpha", "vetted"),
+            ("beta", "weaker-criteria"),
+            ("delta", "vetted"),
+            ("epsilon", "unvetted"),
+            ("gamma", "exempted"),
+        ]
+        .map(|(name, status)| (name.to_string(), status.to_string()))
+    );
+
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&["--cargo-vet", "deny"]));
+    assert!(opt.run_vendor().is_err());
+
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&["--vet-criteria", "safe-to-run"]));
+    assert!(opt.run_vendor().is_ok());
+    let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(content["vet"][1]["status"], "vetted");
+    Ok(())
+}
+
+#[test]
+fn dependency_changes_since_the_previous_tarball_are_reported() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let gitdep_root = tmp_binding.path().join("gitdep");
+    let gitdep = git_fixture(&gitdep_root, "gitdep")?;
+    let olddep = git_fixture(&tmp_binding.path().join("olddep"), "olddep")?;
+    let newdep = git_fixture(&tmp_binding.path().join("newdep"), "newdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    let manifest = |dependency: &str, url: &url::Url| {
+        format!(
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{gitdep}\" }}\n{dependency} = {{ git = \"{url}\" }}\n"
+        )
+    };
+    write_fixture(
+        &src,
+        &[
+            ("Cargo.toml", &manifest("olddep", &olddep)),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let report = tmp_binding.path().join("report.json");
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--dependency-report",
+        &report.to_string_lossy(),
+    ];
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    assert!(outdir.join("vendor.tar.zst").is_file());
+    assert!(!report.exists());
+
+    write_fixture(
+        &gitdep_root,
+        &[(
+            "Cargo.toml",
+            "[package]\nname = \"gitdep\"\nversion = \"0.2.0\"\nedition = \"2021\"\n",
+        )],
+    )?;
+    git_commit_all(&gitdep_root)?;
+    write_fixture(&src, &[("Cargo.toml", &manifest("newdep", &newdep))])?;
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(content["added"][0]["name"], "newdep");
+    assert_eq!(content["removed"][0]["name"], "olddep");
+    assert_eq!(content["upgraded"][0]["name"], "gitdep");
+    assert_eq!(content["upgraded"][0]["from"], "0.1.0");
+    assert_eq!(content["upgraded"][0]["to"], "0.2.0");
+    assert_eq!(content["downgraded"].as_array().map(Vec::len), Some(0));
+    Ok(())
+}
+
+#[test]
+fn dependency_updates_are_added_to_the_changes_file() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let gitdep_root = tmp_binding.path().join("gitdep");
+    let gitdep = git_fixture(&gitdep_root, "gitdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{gitdep}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let status = std::process::Command::new("cargo")
+        .arg("generate-lockfile")
+        .current_dir(&src)
+        .status()?;
+    assert!(status.success());
+    write_fixture(
+        &gitdep_root,
+        &[(
+            "Cargo.toml",
+            "[package]\nname = \"gitdep\"\nversion = \"0.2.0\"\nedition = \"2021\"\n",
+        )],
+    )?;
+    git_commit_all(&gitdep_root)?;
+
+    let changes_file = tmp_binding.path().join("app.changes");
+    let previous_entry = "-------------------------------------------------------------------\nMon Jan 01 00:00:00 UTC 2024 - Packager <packager@example.com>\n\n- Initial package\n";
+    std::fs::write(&changes_file, previous_entry)?;
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--changesdependencies",
+        "true",
+        "--changesauthor",
+        "Packager",
+        "--changesemail",
+        "packager@example.com",
+        "--changesoutfile",
+        &changes_file.to_string_lossy(),
+    ];
+
+    // Without an author, nothing is vendored.
+    let mut opt = cli::Opts::parse_from(args);
+    opt.changesauthor = None;
+    let err = opt.run_vendor().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(std::fs::read_dir(&outdir)?.count(), 0);
+
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&["--update", "false"]));
+    assert!(opt.run_vendor().is_ok());
+    assert_eq!(std::fs::read_to_string(&changes_file)?, previous_entry);
+
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    let content = std::fs::read_to_string(&changes_file)?;
+    let (entry, rest) = content
+        .split_once("\n\n- Update vendored dependencies:\n")
+        .unwrap();
+    assert!(
+        entry.starts_with("-------------------------------------------------------------------\n")
+    );
+    assert!(entry.ends_with(" - Packager <packager@example.com>"));
+    assert!(rest.starts_with("  * gitdep 0.1.0 -> 0.2.0\n\n"));
+    assert!(rest.ends_with(previous_entry));
+
+    assert!(
+        cli::Opts::try_parse_from([
+            "cargo_vendor",
+            "--src",
+            ".",
+            "--outdir",
+            ".",
+            "--changesdependencies",
+            "true"
+        ])
+        .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn unchanged_archives_are_kept() -> io::Result<()> {
+    for method in ["vendor", "registry"] {
+        let tmp_binding = tempfile::TempDir::new()?;
+        let gitdep_root = tmp_binding.path().join("gitdep");
+        let gitdep = git_fixture(&gitdep_root, "gitdep")?;
+        let src = tmp_binding.path().join("src");
+        let outdir = tmp_binding.path().join("out");
+        std::fs::create_dir_all(&outdir)?;
+        write_fixture(
+            &src,
+            &[
+                (
+                    "Cargo.toml",
+                    &format!(
+                        "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{gitdep}\" }}\n"
+                    ),
+                ),
+                ("src/main.rs", "fn main() {}\n"),
+            ],
+        )?;
+        let args = [
+            "cargo_vendor",
+            "--src",
+            &src.to_string_lossy(),
+            "--outdir",
+            &outdir.to_string_lossy(),
+            "--method",
+            method,
+            "--skip-unchanged",
+            "true",
+        ];
+        let archive = outdir.join(format!("{method}.tar.zst"));
+
+        let mut opt = cli::Opts::parse_from(args);
+        assert!(opt.run_vendor().is_ok());
+        let created = std::fs::metadata(&archive)?.modified()?;
+        let extracted = tmp_binding.path().join("extracted");
+        cli::decompress(&Compression::Zst, &extracted, &archive)?;
+        let state = std::fs::read_to_string(extracted.join(".obs-service-cargo.json"))?;
+        assert!(state.contains(&format!("\"method\": \"{method}\"")));
+
+        let mut opt = cli::Opts::parse_from(args);
+        assert!(opt.run_vendor().is_ok());
+        assert_eq!(std::fs::metadata(&archive)?.modified()?, created);
+
+        // Other sources with the same lockfiles, e.g. a new release, don't
+        // matter.
+        let new_src = tmp_binding.path().join("app-0.1.1");
+        write_fixture(
+            &new_src,
+            &[
+                (
+                    "Cargo.toml",
+                    &std::fs::read_to_string(src.join("Cargo.toml"))?,
+                ),
+                ("src/main.rs", "fn main() { println!(\"0.1.1\"); }\n"),
+            ],
+        )?;
+        let new_src = new_src.to_string_lossy();
+        let mut new_args = args;
+        new_args[2] = &new_src;
+        let mut opt = cli::Opts::parse_from(new_args);
+        assert!(opt.run_vendor().is_ok());
+        assert_eq!(std::fs::metadata(&archive)?.modified()?, created);
+
+        write_fixture(
+            &gitdep_root,
+            &[(
+                "Cargo.toml",
+                "[package]\nname = \"gitdep\"\nversion = \"0.2.0\"\nedition = \"2021\"\n",
+            )],
+        )?;
+        git_commit_all(&gitdep_root)?;
+        let mut opt = cli::Opts::parse_from(args);
+        assert!(opt.run_vendor().is_ok());
+        assert_ne!(std::fs::metadata(&archive)?.modified()?, created);
+    }
+    Ok(())
+}
+
+#[test]
+fn tarballs_are_verified_against_the_sources() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let gitdep_root = tmp_binding.path().join("gitdep");
+    let gitdep = git_fixture(&gitdep_root, "gitdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{gitdep}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+    ];
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    let archive = outdir.join("vendor.tar.zst");
+    let created = std::fs::metadata(&archive)?.modified()?;
+
+    // A newer commit upstream must not matter since nothing is updated.
+    write_fixture(
+        &gitdep_root,
+        &[(
+            "Cargo.toml",
+            "[package]\nname = \"gitdep\"\nversion = \"0.2.0\"\nedition = \"2021\"\n",
+        )],
+    )?;
+    git_commit_all(&gitdep_root)?;
+    let policy_report = tmp_binding.path().join("policy.json");
+    let dependency_report = tmp_binding.path().join("dependencies.json");
+    let verify_args = [
+        "--verify-against",
+        &archive.to_string_lossy(),
+        "--policy-report",
+        &policy_report.to_string_lossy(),
+        "--dependency-report",
+        &dependency_report.to_string_lossy(),
+        "--merge-into",
+        "vendor.tar.zst",
+    ];
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&verify_args));
+    assert!(opt.run_vendor().is_ok());
+    assert_eq!(std::fs::metadata(&archive)?.modified()?, created);
+    assert_eq!(std::fs::read_dir(&outdir)?.count(), 1);
+    assert!(!policy_report.exists());
+    assert!(!dependency_report.exists());
+
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(&Compression::Zst, &extracted, &archive)?;
+    std::fs::write(
+        extracted.join("vendor/gitdep-0.1.0/src/lib.rs"),
+        "// patched\n",
+    )?;
+    let tampered = tmp_binding.path().join("tampered.tar");
+    let status = std::process::Command::new("tar")
+        .arg("-cf")
+        .arg(&tampered)
+        .arg("-C")
+        .arg(&extracted)
+        .arg(".")
+        .status()?;
+    assert!(status.success());
+    let verify_args = ["--verify-against", &tampered.to_string_lossy()];
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&verify_args));
+    let err = opt.run_vendor().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().starts_with("Found 1 differences"));
+    Ok(())
+}
+
+#[test]
+fn archives_record_their_provenance() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let gitdep_root = tmp_binding.path().join("gitdep");
+    let gitdep = git_fixture(&gitdep_root, "gitdep")?;
+    let src = tmp_binding.path().join("app-0.1.0");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{gitdep}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let src_tarball = tmp_binding.path().join("app-0.1.0.tar.gz");
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&src_tarball)
+        .arg("-C")
+        .arg(tmp_binding.path())
+        .arg("app-0.1.0")
+        .status()?;
+    assert!(status.success());
+
+    for method in ["vendor", "registry"] {
+        let mut opt = cli::Opts::parse_from([
+            "cargo_vendor",
+            "--src",
+            &src_tarball.to_string_lossy(),
+            "--outdir",
+            &outdir.to_string_lossy(),
+            "--method",
+            method,
+            "--tag",
+            "app",
+        ]);
+        assert!(opt.run_vendor().is_ok());
+        let extracted = tmp_binding.path().join(method);
+        cli::decompress(
+            &Compression::Zst,
+            &extracted,
+            &outdir.join(format!("{method}-app.tar.zst")),
+        )?;
+        let manifest: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(
+            extracted.join(".obs-service-cargo.json"),
+        )?)?;
+        assert_eq!(manifest["method"], method);
+        assert_eq!(manifest["options"]["update"], true);
+        assert_eq!(manifest["options"]["tag"], "app");
+        assert_eq!(manifest["source"]["name"], "app-0.1.0.tar.gz");
+        assert_eq!(
+            manifest["source"]["blake3"],
+            blake3::hash(&std::fs::read(&src_tarball)?)
+                .to_hex()
+                .as_str()
+        );
+        let lockfile_hash = blake3::hash(&std::fs::read(extracted.join("Cargo.lock"))?);
+        assert_eq!(
+            manifest["lockfiles"]["Cargo.lock"],
+            lockfile_hash.to_hex().as_str()
+        );
+        let crates = manifest["crates"].as_array().unwrap();
+        assert_eq!(crates.len(), 1);
+        assert_eq!(crates[0]["name"], "gitdep");
+        assert_eq!(crates[0]["version"], "0.1.0");
+        assert!(
+            crates[0]["source"]
+                .as_str()
+                .unwrap()
+                .starts_with(&format!("git+{gitdep}#"))
+        );
+        assert!(crates[0]["checksum"].is_null());
+    }
+    Ok(())
+}
+
+#[test]
+fn crates_are_shared_through_the_crate_cache() -> io::Result<()> {
+    use obs_service_cargo::crate_cache::CrateCache;
+    use sha2::{Digest, Sha256};
+
+    let tmp_binding = tempfile::TempDir::new()?;
+    let crate_bytes = b"not really a crate";
+    let checksum = format!("{:x}", Sha256::digest(crate_bytes));
+    let lockfile = tmp_binding.path().join("Cargo.lock");
+    std::fs::write(
+        &lockfile,
+        format!(
+            "version = 4\n\n[[package]]\nname = \"app\"\nversion = \"0.1.0\"\ndependencies = [\n \"foo\",\n]\n\n[[package]]\nname = \"foo\"\nversion = \"1.0.0\"\nsource = \"registry+https://github.com/rust-lang/crates.io-index\"\nchecksum = \"{checksum}\"\n"
+        ),
+    )?;
+    let registry_dir = "index.crates.io-0123456789abcdef";
+    let cache = CrateCache::open(&tmp_binding.path().join("cache"))?;
+
+    let first_home = tmp_binding.path().join("first");
+    std::fs::create_dir_all(first_home.join("registry/index").join(registry_dir))?;
+    let downloaded_dir = first_home.join("registry/cache").join(registry_dir);
+    std::fs::create_dir_all(&downloaded_dir)?;
+    std::fs::write(downloaded_dir.join("foo-1.0.0.crate"), crate_bytes)?;
+    assert_eq!(cache.seed(&first_home, &lockfile)?, 0);
+    assert_eq!(cache.harvest(&first_home, &lockfile)?, 1);
+    assert_eq!(cache.harvest(&first_home, &lockfile)?, 0);
+
+    // Before cargo sets up the index, crates are seeded into every directory
+    // seen before. The ones cargo does not use are removed afterwards.
+    let second_home = tmp_binding.path().join("second");
+    assert_eq!(cache.seed(&second_home, &lockfile)?, 1);
+    let seeded = second_home
+        .join("registry/cache")
+        .join(registry_dir)
+        .join("foo-1.0.0.crate");
+    assert_eq!(std::fs::read(&seeded)?, crate_bytes);
+    cache.harvest(&second_home, &lockfile)?;
+    assert!(
+        !second_home
+            .join("registry/cache")
+            .join(registry_dir)
+            .exists()
+    );
+
+    let cached = tmp_binding
+        .path()
+        .join("cache/crates")
+        .join(format!("{checksum}.crate"));
+    std::fs::write(&cached, b"tampered")?;
+    let third_home = tmp_binding.path().join("third");
+    assert_eq!(cache.seed(&third_home, &lockfile)?, 0);
+    assert!(!cached.exists());
+    Ok(())
+}
+
+#[test]
+fn vendoring_offline_takes_crates_from_a_local_source() -> io::Result<()> {
+    use sha2::{Digest, Sha256};
+
+    let tmp_binding = tempfile::TempDir::new()?;
+    let crates = tmp_binding.path().join("crates");
+    write_fixture(
+        &crates.join("foo-1.0.0"),
+        &[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"foo\"\nversion = \"1.0.0\"\nedition = \"2021\"\n",
+            ),
+            ("src/lib.rs", "pub fn foo() {}\n"),
+        ],
+    )?;
+    let status = std::process::Command::new("tar")
+        .args(["-czf", "foo-1.0.0.crate", "foo-1.0.0"])
+        .current_dir(&crates)
+        .status()?;
+    assert!(status.success());
+    std::fs::remove_dir_all(crates.join("foo-1.0.0"))?;
+    let checksum = format!(
+        "{:x}",
+        Sha256::digest(std::fs::read(crates.join("foo-1.0.0.crate"))?)
+    );
+    let lockfile = |extra: &str| {
+        format!(
+            "version = 4\n\n[[package]]\nname = \"app\"\nversion = \"0.1.0\"\ndependencies = [\n \"foo\",\n]\n\n[[package]]\nname = \"foo\"\nversion = \"1.0.0\"\nsource = \"registry+https://github.com/rust-lang/crates.io-index\"\nchecksum = \"{checksum}\"\n{extra}"
+        )
+    };
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nfoo = \"1\"\n",
+            ),
+            ("Cargo.lock", &lockfile("")),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--offline",
+        "true",
+    ];
+
+    let mut opt = cli::Opts::parse_from(args);
+    let err = opt.run_vendor().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let local_registry = ["--local-registry", &crates.to_string_lossy()];
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&local_registry));
+    assert!(opt.run_vendor().is_ok());
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("vendor.tar.zst"),
+    )?;
+    assert!(extracted.join("vendor/foo-1.0.0/src/lib.rs").is_file());
+    let config = std::fs::read_to_string(extracted.join(".cargo/config.toml"))?;
+    assert!(config.contains("replace-with = \"vendored-sources\""));
+
+    let mut opt = cli::Opts::parse_from(
+        args.iter()
+            .chain(&local_registry)
+            .chain(&["--method", "registry"]),
+    );
+    let err = opt.run_vendor().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+    write_fixture(
+        &src,
+        &[(
+            "Cargo.lock",
+            &lockfile(
+                "\n[[package]]\nname = \"bar\"\nversion = \"0.1.0\"\nsource = \"registry+https://github.com/rust-lang/crates.io-index\"\nchecksum = \"0000000000000000000000000000000000000000000000000000000000000000\"\n",
+            ),
+        )],
+    )?;
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&local_registry));
+    let err = opt.run_vendor().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(
+        err.to_string()
+            .starts_with("Found 1 crates that can't be taken from the local source")
+    );
+    Ok(())
+}
+
+/// A git registry index with crates named with three letters, each depending
+/// on the listed crates. Crates are added to the index of earlier calls with
+/// a new commit. Returns the index directory.
+fn registry_index_fixture(
+    root: &std::path::Path,
+    crates: &[(&str, &str, &[&str])],
+) -> io::Result<PathBuf> {
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+
+    let crates_dir = root.join("crates");
+    let index = root.join("index");
+    write_fixture(
+        &index,
+        &[(
+            "config.json",
+            &format!(
+                "{{\"dl\":\"{}{{crate}}-{{version}}.crate\"}}\n",
+                url::Url::from_directory_path(&crates_dir).unwrap()
+            ),
+        )],
+    )?;
+    for (name, version, dependencies) in crates {
+        assert_eq!(name.len(), 3);
+        let manifest_dependencies: String = dependencies
+            .iter()
+            .map(|dependency| format!("{dependency} = \"0.1\"\n"))
+            .collect();
+        let crate_dir = format!("{name}-{version}");
+        write_fixture(
+            &crates_dir.join(&crate_dir),
+            &[
+                (
+                    "Cargo.toml",
+                    &format!(
+                        "[package]\nname = \"{name}\"\nversion = \"{version}\"\nedition = \"2021\"\n\n[dependencies]\n{manifest_dependencies}"
+                    ),
+                ),
+                ("src/lib.rs", "pub fn hello() {}\n"),
+            ],
+        )?;
+        let crate_file = format!("{crate_dir}.crate");
+        let status = std::process::Command::new("tar")
+            .args(["-czf", &crate_file, &crate_dir])
+            .current_dir(&crates_dir)
+            .status()?;
+        assert!(status.success());
+        std::fs::remove_dir_all(crates_dir.join(&crate_dir))?;
+        let checksum = format!(
+            "{:x}",
+            Sha256::digest(std::fs::read(crates_dir.join(&crate_file))?)
+        );
+        let index_dependencies: Vec<String> = dependencies
+            .iter()
+            .map(|dependency| {
+                format!(
+                    "{{\"name\":\"{dependency}\",\"req\":\"^0.1\",\"features\":[],\"optional\":false,\"default_features\":true,\"target\":null,\"kind\":\"normal\"}}"
+                )
+            })
+            .collect();
+        let index_file = index.join(format!("3/{}/{name}", &name[..1]));
+        std::fs::create_dir_all(index_file.parent().unwrap())?;
+        writeln!(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(index_file)?,
+            "{{\"name\":\"{name}\",\"vers\":\"{version}\",\"deps\":[{}],\"cksum\":\"{checksum}\",\"features\":{{}},\"yanked\":false}}",
+            index_dependencies.join(",")
+        )?;
+    }
+    if !index.join(".git").exists() {
+        let status = std::process::Command::new("git")
+            .args(["init", "--quiet", "--initial-branch=main"])
+            .current_dir(&index)
+            .status()?;
+        assert!(status.success());
+    }
+    git_commit_all(&index)?;
+    Ok(index)
+}
+
+#[test]
+fn crates_from_alternate_registries_are_vendored() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let index = registry_index_fixture(tmp_binding.path(), &[("baz", "0.1.0", &[])])?;
+
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nbaz = { version = \"0.1\", registry = \"my-reg\" }\n",
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+    ];
+
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&["--alternate-registry", "my-reg"]));
+    let err = opt.run_vendor().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let registry = format!("my-reg={}", index.display());
+    let mut opt = cli::Opts::parse_from(
+        args.iter()
+            .chain(&["--alternate-registry", registry.as_str()]),
+    );
+    assert!(opt.run_vendor().is_ok());
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("vendor.tar.zst"),
+    )?;
+    assert!(extracted.join("vendor/baz-0.1.0/src/lib.rs").is_file());
+    let config = std::fs::read_to_string(extracted.join(".cargo/config.toml"))?;
+    assert!(config.contains("[registries.my-reg]"));
+    assert!(config.contains("replace-with = \"vendored-sources\""));
+
+    // The sources build offline with nothing but the tarball.
+    let build = tmp_binding.path().join("build");
+    write_fixture(
+        &build,
+        &[
+            (
+                "Cargo.toml",
+                &std::fs::read_to_string(src.join("Cargo.toml"))?,
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let status = std::process::Command::new("cp")
+        .args(["-a", "vendor", ".cargo", "Cargo.lock"])
+        .arg(&build)
+        .current_dir(&extracted)
+        .status()?;
+    assert!(status.success());
+    let status = std::process::Command::new("cargo")
+        .args(["metadata", "--offline", "--locked", "--format-version=1"])
+        .env("CARGO_HOME", tmp_binding.path().join("empty-cargo-home"))
+        .stdout(std::process::Stdio::null())
+        .current_dir(&build)
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn registry_method_creates_a_local_registry() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let index = registry_index_fixture(
+        tmp_binding.path(),
+        &[
+            ("qux", "0.1.0", &[]),
+            ("baz", "0.1.0", &["qux"]),
+            ("zap", "0.1.0", &[]),
+        ],
+    )?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    let manifest = "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nbaz = \"0.1\"\n";
+    write_fixture(
+        &src,
+        &[
+            ("Cargo.toml", manifest),
+            ("src/main.rs", "fn main() {}\n"),
+            (
+                ".cargo/config.toml",
+                &format!(
+                    "[source.crates-io]\nreplace-with = \"mirror\"\n\n[source.mirror]\nregistry = \"{}\"\n",
+                    url::Url::from_directory_path(&index).unwrap()
+                ),
+            ),
+        ],
+    )?;
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--method",
+        "registry",
+        "--registry-layout",
+        "local-registry",
+    ]);
+    assert!(opt.run_vendor().is_ok());
+
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("registry.tar.zst"),
+    )?;
+    let local_registry = extracted.join(".cargo/local-registry");
+    assert!(local_registry.join("baz-0.1.0.crate").is_file());
+    assert!(local_registry.join("qux-0.1.0.crate").is_file());
+    assert!(!local_registry.join("zap-0.1.0.crate").exists());
+    let baz_index = std::fs::read_to_string(local_registry.join("index/3/b/baz"))?;
+    assert!(baz_index.contains("\"name\":\"qux\""));
+    assert!(!extracted.join(".cargo/registry").exists());
+    let config = std::fs::read_to_string(extracted.join(".cargo/config.toml"))?;
+    assert!(config.contains("local-registry = \".cargo/local-registry\""));
+
+    // The sources build offline with the tarball and without `CARGO_HOME`.
+    let build = tmp_binding.path().join("build");
+    write_fixture(
+        &build,
+        &[("Cargo.toml", manifest), ("src/main.rs", "fn main() {}\n")],
+    )?;
+    let status = std::process::Command::new("cp")
+        .args(["-a", ".cargo", "Cargo.lock"])
+        .arg(&build)
+        .current_dir(&extracted)
+        .status()?;
+    assert!(status.success());
+    let status = std::process::Command::new("cargo")
+        .args(["metadata", "--offline", "--locked", "--format-version=1"])
+        .env("CARGO_HOME", tmp_binding.path().join("empty-cargo-home"))
+        .stdout(std::process::Stdio::null())
+        .current_dir(&build)
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn registry_method_prunes_what_is_not_in_the_lockfiles() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let index = registry_index_fixture(
+        tmp_binding.path(),
+        &[("qux", "0.1.0", &[]), ("baz", "0.1.0", &["qux"])],
+    )?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    let files = [
+        (
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nbaz = \"0.1\"\n".to_string(),
+        ),
+        ("src/main.rs", "fn main() {}\n".to_string()),
+        (
+            ".cargo/config.toml",
+            format!(
+                "[source.crates-io]\nreplace-with = \"mirror\"\n\n[source.mirror]\nregistry = \"{}\"\n",
+                url::Url::from_directory_path(&index).unwrap()
+            ),
+        ),
+    ];
+    let files: Vec<(&str, &str)> = files
+        .iter()
+        .map(|(path, content)| (*path, content.as_str()))
+        .collect();
+    write_fixture(&src, &files)?;
+    let status = std::process::Command::new("cargo")
+        .arg("generate-lockfile")
+        .env("CARGO_HOME", tmp_binding.path().join("cargo-home"))
+        .current_dir(&src)
+        .status()?;
+    assert!(status.success());
+    // The update drops qux, which cargo still looked up in the index.
+    registry_index_fixture(tmp_binding.path(), &[("baz", "0.1.1", &[])])?;
+
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--method",
+        "registry",
+    ]);
+    assert!(opt.run_vendor().is_ok());
+
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("registry.tar.zst"),
+    )?;
+    let lockfile = std::fs::read_to_string(extracted.join("Cargo.lock"))?;
+    assert!(lockfile.contains("0.1.1") && !lockfile.contains("qux"));
+    let registry_dir = |kind: &str| -> io::Result<PathBuf> {
+        let mut dirs = std::fs::read_dir(extracted.join(".cargo/registry").join(kind))?;
+        Ok(dirs.next().unwrap()?.path())
+    };
+    let index_cache = registry_dir("index")?.join(".cache");
+    assert!(index_cache.join("3/b/baz").is_file());
+    assert!(!index_cache.join("3/q").exists());
+    let crates = registry_dir("cache")?;
+    assert!(crates.join("baz-0.1.1.crate").is_file());
+    assert_eq!(std::fs::read_dir(&crates)?.count(), 1);
+
+    // The pruned registry still works offline.
+    let build = tmp_binding.path().join("build");
+    write_fixture(&build, &files)?;
+    std::fs::copy(extracted.join("Cargo.lock"), build.join("Cargo.lock"))?;
+    let status = std::process::Command::new("cargo")
+        .args(["metadata", "--offline", "--locked", "--format-version=1"])
+        .env("CARGO_HOME", extracted.join(".cargo"))
+        .stdout(std::process::Stdio::null())
+        .current_dir(&build)
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn registry_method_keeps_only_locked_git_revisions() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let dependency = tmp_binding.path().join("dependency");
+    let url = git_fixture(&dependency, "dependency")?;
+    let rev_parse = |dir: &std::path::Path, revision: &str| -> io::Result<String> {
+        let output = std::process::Command::new("git")
+            .args(["rev-parse", revision])
+            .current_dir(dir)
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let locked = rev_parse(&dependency, "HEAD")?;
+    write_fixture(&dependency, &[("src/lib.rs", "pub fn newer() {}\n")])?;
+    git_commit_all(&dependency)?;
+    let newer = rev_parse(&dependency, "HEAD")?;
+
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    let manifest = format!(
+        "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ndependency = {{ git = \"{url}\", rev = \"{locked}\" }}\n"
+    );
+    write_fixture(
+        &src,
+        &[("Cargo.toml", &manifest), ("src/main.rs", "fn main() {}\n")],
+    )?;
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--method",
+        "registry",
+    ]);
+    assert!(opt.run_vendor().is_ok());
+    // Only the verified tarball is left in outdir.
+    assert_eq!(std::fs::read_dir(&outdir)?.count(), 1);
+
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("registry.tar.zst"),
+    )?;
+    assert!(!extracted.join(".cargo/git/checkouts").exists());
+    let mut dbs = std::fs::read_dir(extracted.join(".cargo/git/db"))?;
+    let db = dbs.next().unwrap()?.path();
+    assert!(dbs.next().is_none());
+    assert_eq!(rev_parse(&db, &format!("{locked}^{{commit}}"))?, locked);
+    let has_newer = std::process::Command::new("git")
+        .args(["cat-file", "-e", &newer])
+        .current_dir(&db)
+        .status()?;
+    assert!(!has_newer.success());
+
+    // The git dependency is checked out again from the database offline.
+    let build = tmp_binding.path().join("build");
+    write_fixture(
+        &build,
+        &[("Cargo.toml", &manifest), ("src/main.rs", "fn main() {}\n")],
+    )?;
+    std::fs::copy(extracted.join("Cargo.lock"), build.join("Cargo.lock"))?;
+    let status = std::process::Command::new("cargo")
+        .args(["metadata", "--offline", "--locked", "--format-version=1"])
+        .env("CARGO_HOME", extracted.join(".cargo"))
+        .stdout(std::process::Stdio::null())
+        .current_dir(&build)
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn vendor_tarballs_of_subprojects_are_merged() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let index = registry_index_fixture(
+        tmp_binding.path(),
+        &[("qux", "0.1.0", &[]), ("baz", "0.1.0", &[])],
+    )?;
+    let registry = format!("my-reg={}", index.display());
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    let manifest = |name: &str, dependencies: &str| {
+        format!(
+            "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n{dependencies}"
+        )
+    };
+    let one = manifest(
+        "one",
+        "qux = { version = \"0.1\", registry = \"my-reg\" }\n",
+    );
+    let two = manifest(
+        "two",
+        "baz = { version = \"0.1\", registry = \"my-reg\" }\nqux = { version = \"0.1\", registry = \"my-reg\" }\n",
+    );
+    write_fixture(
+        &src,
+        &[
+            ("one/Cargo.toml", &one),
+            ("one/src/main.rs", "fn main() {}\n"),
+            ("two/Cargo.toml", &two),
+            ("two/src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--alternate-registry",
+        &registry,
+    ];
+
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&["--manifest-path", "one/Cargo.toml"]));
+    assert!(opt.run_vendor().is_ok());
+    let mut opt = cli::Opts::parse_from(args.iter().chain(&[
+        "--manifest-path",
+        "two/Cargo.toml",
+        "--merge-into",
+        "vendor.tar.zst",
+    ]));
+    assert!(opt.run_vendor().is_ok());
+
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("vendor.tar.zst"),
+    )?;
+    assert!(extracted.join("vendor/baz-0.1.0/src/lib.rs").is_file());
+    assert!(extracted.join("vendor/qux-0.1.0/src/lib.rs").is_file());
+    assert!(!extracted.join("one/vendor").exists());
+    assert!(!extracted.join("two/vendor").exists());
+    for subproject in ["one", "two"] {
+        let config =
+            std::fs::read_to_string(extracted.join(subproject).join(".cargo/config.toml"))?;
+        assert!(config.contains("directory = \"../vendor\""));
+        assert!(config.contains("[registries.my-reg]"));
+    }
+    let manifest = ArchiveManifest::read(&extracted)?.unwrap();
+    assert_eq!(manifest.lockfiles.len(), 2);
+
+    // Both subprojects build offline with nothing but the merged tarball.
+    let build = tmp_binding.path().join("build");
+    let status = std::process::Command::new("cp")
+        .args(["-a", "."])
+        .arg(&build)
+        .current_dir(&extracted)
+        .status()?;
+    assert!(status.success());
+    for (subproject, manifest) in [("one", &one), ("two", &two)] {
+        write_fixture(
+            &build.join(subproject),
+            &[("Cargo.toml", manifest), ("src/main.rs", "fn main() {}\n")],
+        )?;
+        let status = std::process::Command::new("cargo")
+            .args(["metadata", "--offline", "--locked", "--format-version=1"])
+            .env("CARGO_HOME", tmp_binding.path().join("empty-cargo-home"))
+            .stdout(std::process::Stdio::null())
+            .current_dir(build.join(subproject))
+            .status()?;
+        assert!(status.success());
+    }
+    Ok(())
+}
+
+#[test]
+fn cargo_vendor_services_of_a_service_file_are_run() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let index = registry_index_fixture(
+        tmp_binding.path(),
+        &[("qux", "0.1.0", &[]), ("baz", "0.1.0", &[])],
+    )?;
+    let src = tmp_binding.path().join("project");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    let manifest = |name: &str, dependency: &str| {
+        format!(
+            "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n{dependency} = {{ version = \"0.1\", registry = \"my-reg\" }}\n"
+        )
+    };
+    write_fixture(
+        &src,
+        &[
+            ("one/Cargo.toml", &manifest("one", "qux")),
+            ("one/src/main.rs", "fn main() {}\n"),
+            ("two/Cargo.toml", &manifest("two", "baz")),
+            ("two/src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let tarball = tmp_binding.path().join("project-0.1.0.tar.gz");
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&tarball)
+        .arg("project")
+        .current_dir(tmp_binding.path())
+        .status()?;
+    assert!(status.success());
+
+    let service = |tag: &str, extra: &str| {
+        format!(
+            r#"  <service name="cargo_vendor" mode="manual">
+    <param name="src">{}</param>
+    <param name="cargotoml">{tag}/Cargo.toml</param>
+    <param name="alternate-registry">my-reg={}</param>
+    <param name="tag">{tag}</param>{extra}
+  </service>
+"#,
+            tarball.display(),
+            index.display()
+        )
+    };
+    let service_file = tmp_binding.path().join("_service");
+    std::fs::write(
+        &service_file,
+        format!(
+            "<services>\n  <service name=\"download_files\" mode=\"manual\" />\n{}{}{}{}</services>\n",
+            service("one", ""),
+            service("broken", "\n    <param name=\"no-such-param\">true</param>"),
+            service("two", ""),
+            service("one", "")
+                .replace("mode=\"manual\"", "mode=\"buildtime\"")
+                .replace(
+                    ">one</param>\n  </service>",
+                    ">buildtime</param>\n  </service>"
+                ),
+        ),
+    )?;
+
+    // The configuration file next to the service file applies, not one in
+    // outdir.
+    std::fs::write(
+        tmp_binding.path().join("_cargo_vendor.toml"),
+        "compression = \"gz\"\n",
+    )?;
+    std::fs::write(outdir.join("_cargo_vendor.toml"), "compression = \"xz\"\n")?;
+
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--from-service",
+        &service_file.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+    ]);
+    // The broken service fails the run, but the others still produce their
+    // tarballs.
+    assert!(opt.run_vendor().is_err());
+    assert!(outdir.join("vendor-one.tar.gz").is_file());
+    assert!(outdir.join("vendor-two.tar.gz").is_file());
+    assert!(!outdir.join("vendor-broken.tar.gz").exists());
+    // Like `osc`, services that only run in the build are skipped.
+    assert!(!outdir.join("vendor-buildtime.tar.gz").exists());
+
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Gz,
+        &extracted,
+        &outdir.join("vendor-two.tar.gz"),
+    )?;
+    assert!(extracted.join("two/vendor/baz-0.1.0/src/lib.rs").is_file());
+    let manifest = ArchiveManifest::read(&extracted)?.unwrap();
+    assert_eq!(manifest.source.name, "project-0.1.0.tar.gz");
+    Ok(())
+}
+
+#[test]
+fn bulk_update_reports_changed_and_unchanged_packages() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let index = registry_index_fixture(tmp_binding.path(), &[("baz", "0.1.0", &[])])?;
+    let package = tmp_binding.path().join("home:user:branches:devel:rust:app");
+    let project = tmp_binding.path().join("app-0.1.0");
+    write_fixture(
+        &project,
+        &[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nbaz = { version = \"0.1\", registry = \"my-reg\" }\n",
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    std::fs::create_dir_all(&package)?;
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(package.join("app-0.1.0.tar.gz"))
+        .arg("app-0.1.0")
+        .current_dir(tmp_binding.path())
+        .status()?;
+    assert!(status.success());
+    // The source is only given as the prefix of the tarball name.
+    std::fs::write(
+        package.join("_service"),
+        format!(
+            r#"<services>
+  <service name="download_files" mode="manual" />
+  <service name="cargo_vendor" mode="manual">
+    <param name="srcdir">app</param>
+    <param name="alternate-registry">my-reg={}</param>
+  </service>
+</services>
+"#,
+            index.display()
+        ),
+    )?;
+    let not_a_package = tmp_binding.path().join("not-a-package");
+    std::fs::create_dir_all(&not_a_package)?;
+
+    let report = tmp_binding.path().join("report.json");
+    let bulk_update = |packages: &[&std::path::Path]| {
+        let mut args = vec![
+            "cargo_vendor".to_string(),
+            "bulk-update".to_string(),
+            "--report".to_string(),
+            report.to_string_lossy().to_string(),
+        ];
+        args.extend(packages.iter().map(|p| p.to_string_lossy().to_string()));
+        cli::Opts::parse_from(args).run_vendor()
+    };
+
+    assert!(bulk_update(&[&package, &not_a_package]).is_err());
+    assert!(package.join("vendor.tar.zst").is_file());
+    let reports: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(reports[0]["status"], "changed");
+    assert!(
+        reports[0]["changes-entry"]
+            .as_str()
+            .unwrap()
+            .contains("* Added baz 0.1.0")
+    );
+    assert_eq!(reports[1]["status"], "error");
+
+    assert!(bulk_update(&[&package]).is_ok());
+    let reports: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(reports[0]["status"], "unchanged");
+    assert!(reports[0]["changes-entry"].is_null());
+    Ok(())
+}
+
+#[test]
+fn settings_of_the_configuration_file_are_overridden_by_parameters() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let package = tmp_binding.path();
+    std::fs::write(
+        package.join(obs_service_cargo::config_file::CONFIG_FILE_NAME),
+        r#"method = "registry"
+tag = "from-file"
+update = false
+crate-cache = "cache"
+
+[[manifest]]
+path = "a/Cargo.toml"
+update-crates = ["foo@1.0.0"]
+
+[[accepted-risk]]
+id = "RUSTSEC-2023-0044"
+reason = "The affected function is never called."
+"#,
+    )?;
+    let opts = obs_service_cargo::config_file::parse_with_config_file(
+        [
+            "cargo_vendor",
+            "--src",
+            "app.tar.gz",
+            "--outdir",
+            ".",
+            "--tag",
+            "from-cli",
+        ],
+        package,
+    )?;
+    assert!(matches!(opts.method, Method::Registry));
+    assert_eq!(opts.tag.as_deref(), Some("from-cli"));
+    assert!(!opts.update);
+    assert_eq!(opts.crate_cache, Some(package.join("cache")));
+    assert_eq!(opts.manifest_path, vec![PathBuf::from("a/Cargo.toml")]);
+    assert_eq!(
+        opts.update_crate,
+        vec!["foo@1.0.0+a/Cargo.toml".to_string()]
+    );
+    assert_eq!(
+        opts.i_accept_the_risk,
+        vec!["RUSTSEC-2023-0044".to_string()]
+    );
+
+    std::fs::write(
+        package.join("strict.toml"),
+        "[[accepted-risk]]\nid = \"CVE-2023-1234\"\nreason = \"Not reachable.\"\n",
+    )?;
+    let err = obs_service_cargo::config_file::parse_with_config_file(
+        [
+            "cargo_vendor",
+            "--src",
+            "app.tar.gz",
+            "--outdir",
+            ".",
+            "--config",
+            "strict.toml",
+        ],
+        package,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("accepted-risk[0].id"));
+
+    std::fs::write(package.join("typo.toml"), "versioned-dir = true\n")?;
+    let err = obs_service_cargo::config_file::parse_with_config_file(
+        [
+            "cargo_vendor",
+            "--src",
+            "app.tar.gz",
+            "--outdir",
+            ".",
+            "--config",
+            "typo.toml",
+        ],
+        package,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("versioned-dir"));
+    Ok(())
+}
+
+#[test]
+fn upstream_hints_are_defaults_beneath_packager_options() -> io::Result<()> {
+    use obs_service_cargo::upstream_hints::{UpstreamHints, apply_upstream_hints};
+
+    let tmp_binding = tempfile::TempDir::new()?;
+    let src = tmp_binding.path();
+    write_fixture(
+        src,
+        &[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["app", "xtask"]
+
+[workspace.metadata.obs-service-cargo]
+manifests = ["xtask/Cargo.toml", "../outside/Cargo.toml"]
+features = ["cli"]
+dev-dependencies = true
+unknown-hint = 1
+
+[package]
+name = "app"
+version = "0.1.0"
+
+[package.metadata.obs-service-cargo]
+dev-dependencies = false
+platforms = ["*-unknown-linux-musl"]
+"#,
+            ),
+            (
+                "xtask/Cargo.toml",
+                "[package]\nname = \"xtask\"\nversion = \"0.1.0\"\n",
+            ),
+        ],
+    )?;
+
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        "app.tar.gz",
+        "--outdir",
+        ".",
+        "--filter",
+        "true",
+    ]);
+    apply_upstream_hints(src, &mut opt)?;
+    // The manifest outside of the sources is skipped.
+    assert_eq!(opt.manifest_path, vec![PathBuf::from("xtask/Cargo.toml")]);
+    assert_eq!(
+        opt.upstream_hints,
+        Some(UpstreamHints {
+            manifests: Vec::new(),
+            features: vec!["cli".to_string()],
+            platforms: vec!["*-unknown-linux-musl".to_string()],
+            dev_dependencies: Some(false),
+        })
+    );
+    // The hints are recorded, so a tarball vendored with other hints is not
+    // kept as unchanged.
+    let options = obs_service_cargo::archive_manifest::ManifestOptions::from(&opt);
+    assert_eq!(options.upstream_hints, opt.upstream_hints);
+    opt.upstream_hints = Some(UpstreamHints::default());
+    assert_ne!(
+        obs_service_cargo::archive_manifest::ManifestOptions::from(&opt),
+        options
+    );
+
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        "app.tar.gz",
+        "--outdir",
+        ".",
+        "--cargotoml",
+        "app/Cargo.toml",
+    ]);
+    apply_upstream_hints(src, &mut opt)?;
+    assert_eq!(opt.manifest_path, vec![PathBuf::from("app/Cargo.toml")]);
+    // Without `filter`, features and platforms don't apply.
+    assert!(opt.upstream_hints.is_none());
+    Ok(())
+}
+
+#[test]
+fn service_definition_matches_the_options() -> io::Result<()> {
+    use obs_service_cargo::service_definition::{check_service_definition, service_definition};
+
+    let installed = PathBuf::from(MANIFEST_DIR).join("../cargo_vendor.service");
+    // Regenerate with `cargo_vendor service-definition --output cargo_vendor.service`.
+    check_service_definition(&installed)?;
+
+    let tmp_binding = tempfile::TempDir::new()?;
+    let stale = tmp_binding.path().join("cargo_vendor.service");
+    std::fs::write(
+        &stale,
+        service_definition().replace(
+            "</service>",
+            "   <parameter name=\"strategy\">\n      <description>Legacy argument.</description>\n   </parameter>\n</service>",
+        ),
+    )?;
+    let err = check_service_definition(&stale).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    Ok(())
+}
+
+#[test]
+fn dry_run_shows_the_plan_without_vendoring() -> io::Result<()> {
+    use obs_service_cargo::plan::{ManifestRole, Plan};
+
+    let tmp_binding = tempfile::TempDir::new()?;
+    let project = tmp_binding.path().join("project");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &project,
+        &[
+            ("README.md", "# project\n"),
+            (
+                "app/Cargo.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo = \"1\"\n",
+            ),
+            ("app/Cargo.lock", "version = 3\n"),
+            (
+                "app/sub/Cargo.toml",
+                "[package]\nname = \"sub\"\nversion = \"0.1.0\"\n",
+            ),
+        ],
+    )?;
+
+    let opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        &project.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--custom-root",
+        "app",
+        "--cargotoml",
+        "sub/Cargo.toml",
+        "--tag",
+        "app",
+        "--update",
+        "false",
+        "--respect-lockfile",
+        "true",
+        "--update-crate",
+        "foo@1.2.3+sub/Cargo.toml",
+        "--update-crate",
+        "bar@latest",
+    ]);
+    let plan = Plan::new(&project, &project.join("app"), &opt)?;
+    assert_eq!(plan.manifests.len(), 2);
+    assert_eq!(plan.manifests[0].role, ManifestRole::Root);
+    assert_eq!(plan.manifests[0].manifest, PathBuf::from("app/Cargo.toml"));
+    assert!(plan.manifests[0].has_dependencies);
+    assert!(plan.manifests[0].lockfile_exists);
+    assert!(plan.manifests[0].locked);
+    assert_eq!(plan.manifests[1].role, ManifestRole::Extra);
+    assert!(!plan.manifests[1].lockfile_exists);
+    assert_eq!(
+        plan.updates,
+        vec!["update foo to exactly 1.2.3 in sub/Cargo.toml".to_string()]
+    );
+    assert_eq!(plan.problems.len(), 1);
+    assert!(plan.problems[0].contains("bar@latest"));
+    assert_eq!(plan.archive, outdir.join("vendor-app.tar.zst"));
+
+    // A wrong custom root is caught before cargo runs.
+    let mut opt = cli::Opts::parse_from([
+        "cargo_vendor",
+        "--src",
+        &project.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--custom-root",
+        "ap",
+        "--dry-run",
+        "true",
+    ]);
+    let err = opt.run_vendor().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(std::fs::read_dir(&outdir)?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn check_finds_stale_tarballs_offline() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let index = registry_index_fixture(
+        tmp_binding.path(),
+        &[("qux", "0.1.0", &[]), ("baz", "0.1.0", &[])],
+    )?;
+    let registry = format!("my-reg={}", index.display());
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    let manifest = |dependencies: &str| {
+        format!(
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n{dependencies}"
+        )
+    };
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &manifest("qux = { version = \"0.1\", registry = \"my-reg\" }\n"),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--alternate-registry",
+        &registry,
+    ];
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    let check = || cli::Opts::parse_from(args.iter().chain(&["--check", "true"])).run_vendor();
+    assert!(check().is_ok());
+
+    // Checking the sources directly leaves them untouched.
+    let entries = || -> io::Result<Vec<PathBuf>> {
+        let mut entries = std::fs::read_dir(&src)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        Ok(entries)
+    };
+    let before = entries()?;
+    let check_opt = cli::Opts::parse_from(args.iter().chain(&["--check", "true"]));
+    assert!(check_staleness(&src, &src, &check_opt).is_ok());
+    assert_eq!(entries()?, before);
+
+    // Upstream locks a version the tarball does not have.
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("vendor.tar.zst"),
+    )?;
+    let lockfile = std::fs::read_to_string(extracted.join("Cargo.lock"))?;
+    let bumped = lockfile.replace(
+        "name = \"qux\"\nversion = \"0.1.0\"",
+        "name = \"qux\"\nversion = \"0.1.1\"",
+    );
+    assert_ne!(lockfile, bumped);
+    std::fs::write(src.join("Cargo.lock"), bumped)?;
+    let err = check().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("Re-run cargo_vendor"));
+
+    // Upstream adds a dependency.
+    std::fs::remove_file(src.join("Cargo.lock"))?;
+    std::fs::write(
+        src.join("Cargo.toml"),
+        manifest(
+            "baz = { version = \"0.1\", registry = \"my-reg\" }\nqux = { version = \"0.1\", registry = \"my-reg\" }\n",
+        ),
+    )?;
+    let err = check().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    Ok(())
+}
+
+#[test]
+fn check_finds_git_dependencies_moved_to_another_revision() -> io::Result<()> {
+    let tmp_binding = tempfile::TempDir::new()?;
+    let gitdep_root = tmp_binding.path().join("gitdep");
+    let gitdep = git_fixture(&gitdep_root, "gitdep")?;
+    let src = tmp_binding.path().join("src");
+    let outdir = tmp_binding.path().join("out");
+    std::fs::create_dir_all(&outdir)?;
+    write_fixture(
+        &src,
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ngitdep = {{ git = \"{gitdep}\" }}\n"
+                ),
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    )?;
+    let args = [
+        "cargo_vendor",
+        "--src",
+        &src.to_string_lossy(),
+        "--outdir",
+        &outdir.to_string_lossy(),
+        "--update",
+        "false",
+    ];
+    let mut opt = cli::Opts::parse_from(args);
+    assert!(opt.run_vendor().is_ok());
+    let check = || cli::Opts::parse_from(args.iter().chain(&["--check", "true"])).run_vendor();
+    assert!(check().is_ok());
+
+    // Upstream moves the git dependency to a new commit of the same version.
+    let extracted = tmp_binding.path().join("extracted");
+    cli::decompress(
+        &Compression::Zst,
+        &extracted,
+        &outdir.join("vendor.tar.zst"),
+    )?;
+    let lockfile = std::fs::read_to_string(extracted.join("Cargo.lock"))?;
+    let rev_parse = || -> io::Result<String> {
+        let output = std::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(&gitdep_root)
+            .output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let locked = rev_parse()?;
+    assert!(lockfile.contains(&locked));
+    std::fs::write(gitdep_root.join("README.md"), "moved\n")?;
+    git_commit_all(&gitdep_root)?;
+    let moved = rev_parse()?;
+    std::fs::write(src.join("Cargo.lock"), lockfile.replace(&locked, &moved))?;
+    let err = check().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // Vendoring with updates may move git dependencies.
+    let check = cli::Opts::parse_from(
+        args.iter()
+            .take(5)
+            .chain(&["--update", "true", "--check", "true"]),
+    )
+    .run_vendor();
+    assert!(check.is_ok());
+    Ok(())
+}